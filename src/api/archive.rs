@@ -0,0 +1,165 @@
+use crate::api::*;
+use std::io::Read;
+use std::path::Path;
+use zip::read::ZipArchive;
+
+/// Most entries a map zip may have. Maps are a handful of difficulties, a song and a cover.
+const MAX_ENTRIES: usize = 512;
+/// Most bytes a map zip may extract to.
+const MAX_TOTAL_SIZE: u64 = 256 * 1024 * 1024;
+/// Highest uncompressed to compressed ratio allowed for entries bigger than `RATIO_MIN_SIZE`.
+const MAX_COMPRESSION_RATIO: u64 = 200;
+/// Entries smaller than this are too small to be a zip bomb, however well they compress.
+const RATIO_MIN_SIZE: u64 = 1024 * 1024;
+/// File types a map is made of, anything else means the zip isn't a map.
+const ALLOWED_EXTENSIONS: [&str; 6] = ["dat", "egg", "ogg", "png", "jpg", "jpeg"];
+
+/// Extracts a map zip into `dest`, rejecting it with `APIErr::UnsafeArchive` if any entry would
+/// land outside of `dest`, isn't a map file, or it extracts to far more than a map should.
+///
+/// `dest` may be left partially extracted on error.
+pub(in crate::api) fn extract_map(zip_path: &Path, dest: &Path) -> Result<(), APIErr> {
+    let unsafe_archive = |reason: String| Err(APIErr::UnsafeArchive { reason });
+    let mut zip =
+        ZipArchive::new(std::fs::File::open(zip_path).with_path(zip_path)?).with_path(zip_path)?;
+    if zip.len() > MAX_ENTRIES {
+        return unsafe_archive(format!("it has {} files", zip.len()));
+    }
+
+    let mut remaining = MAX_TOTAL_SIZE;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).with_path(zip_path)?;
+        // None for absolute paths and any path using `..` to climb out
+        let relative_path = match entry.enclosed_name() {
+            Some(relative_path) => relative_path.to_path_buf(),
+            None => return unsafe_archive(format!("{} is outside the song", entry.name())),
+        };
+        let path = dest.join(&relative_path);
+
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path).with_path(&path)?;
+            continue;
+        }
+
+        let allowed = relative_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                ALLOWED_EXTENSIONS
+                    .iter()
+                    .any(|allowed| ext.eq_ignore_ascii_case(allowed))
+            });
+        if !allowed {
+            return unsafe_archive(format!("{} isn't a map file", relative_path.display()));
+        }
+        if entry.size() > RATIO_MIN_SIZE
+            && entry.size() / entry.compressed_size().max(1) > MAX_COMPRESSION_RATIO
+        {
+            return unsafe_archive(format!(
+                "{} is compressed too well",
+                relative_path.display()
+            ));
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_path(parent)?;
+        }
+        let mut file = std::fs::File::create(&path).with_path(&path)?;
+        // the sizes in the zip's headers can lie, so count what actually comes out
+        let written =
+            io::copy(&mut (&mut entry).take(remaining + 1), &mut file).with_path(&path)?;
+        if written > remaining {
+            return unsafe_archive(String::from("it extracts to too much"));
+        }
+        remaining -= written;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    /// Zips `entries` of name and contents into `dir`, and extracts them into `dir/out`.
+    fn extract(dir: &Path, entries: &[(&str, &[u8])]) -> Result<(), APIErr> {
+        let zip_path = dir.join("map.zip");
+        let mut zip = ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        for (name, contents) in entries {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let out = dir.join("out");
+        std::fs::create_dir(&out).unwrap();
+        extract_map(&zip_path, &out)
+    }
+
+    fn is_unsafe(result: Result<(), APIErr>) -> bool {
+        matches!(result, Err(APIErr::UnsafeArchive { .. }))
+    }
+
+    #[test]
+    fn extracts_a_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = extract(
+            dir.path(),
+            &[
+                ("Info.dat", b"{}"),
+                ("Expert.dat", b"{}"),
+                ("song.egg", b"ogg"),
+                ("Cover.JPG", b"jpg"),
+            ],
+        );
+        assert!(result.is_ok());
+        assert!(dir.path().join("out/Info.dat").is_file());
+        assert!(dir.path().join("out/Cover.JPG").is_file());
+    }
+
+    #[test]
+    fn rejects_entries_outside_the_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(is_unsafe(extract(dir.path(), &[("../escaped.dat", b"{}")])));
+        assert!(!dir.path().join("escaped.dat").exists());
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(is_unsafe(extract(
+            dir.path(),
+            &[("a/../../escaped.dat", b"{}")]
+        )));
+        assert!(!dir.path().join("escaped.dat").exists());
+
+        let dir = tempfile::tempdir().unwrap();
+        let absolute = dir.path().join("absolute.dat");
+        let name = absolute.to_str().unwrap();
+        assert!(is_unsafe(extract(dir.path(), &[(name, b"{}")])));
+        assert!(!absolute.exists());
+    }
+
+    #[test]
+    fn rejects_files_that_arent_map_files() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(is_unsafe(extract(
+            dir.path(),
+            &[("Info.dat", b"{}"), ("run.exe", b"MZ")]
+        )));
+    }
+
+    #[test]
+    fn rejects_oversized_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        // zeros compress far better than any real song
+        let zeros = vec![0; 4 * RATIO_MIN_SIZE as usize];
+        assert!(is_unsafe(extract(dir.path(), &[("song.egg", &zeros)])));
+
+        let dir = tempfile::tempdir().unwrap();
+        let names: Vec<String> = (0..=MAX_ENTRIES).map(|i| format!("{}.dat", i)).collect();
+        let entries: Vec<(&str, &[u8])> = names
+            .iter()
+            .map(|name| (name.as_str(), &b"{}"[..]))
+            .collect();
+        assert!(is_unsafe(extract(dir.path(), &entries)));
+    }
+}
@@ -1,7 +1,10 @@
 use crate::api::*;
-use std::io::Cursor;
-use std::path::PathBuf;
-use zip::read::ZipArchive;
+use crate::cache;
+use crate::map_hash;
+use crate::util::sanitize_folder_name;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
 
 impl std::fmt::Display for SongInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -10,64 +13,557 @@ impl std::fmt::Display for SongInfo {
 }
 
 const BSABER_ADDR: &str = "https://api.beatsaver.com";
+/// Folder inside the songs folder that downloads in progress are kept in.
+pub const STAGING_DIR: &str = ".beat-sharer-staging";
+/// The most keys BeatSaver accepts in one `/maps/ids` or `/maps/hash` request.
+pub const MAX_IDS_PER_REQUEST: usize = 50;
 
-pub(in crate::api) async fn get_song_info(id: String) -> Result<SongInfo, APIErr> {
+/// The parts of BeatSaver's `MapDetail` response we use.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MapDetail {
+    id: String,
+    uploader: UserDetail,
+    metadata: MapDetailMetadata,
+    stats: MapStats,
+    uploaded: Option<String>,
+    #[serde(default)]
+    versions: Vec<MapVersion>,
+}
+
+#[derive(serde::Deserialize)]
+struct UserDetail {
+    name: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MapDetailMetadata {
+    bpm: f32,
+    duration: u32,
+    song_name: String,
+    level_author_name: String,
+}
+
+#[derive(serde::Deserialize)]
+struct MapStats {
+    #[serde(default)]
+    score: f32,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MapVersion {
+    hash: String,
+    state: String,
+    created_at: Option<String>,
+    #[serde(rename = "downloadURL")]
+    download_url: String,
+    #[serde(default)]
+    diffs: Vec<MapDifficulty>,
+}
+
+#[derive(serde::Deserialize)]
+struct MapDifficulty {
+    characteristic: String,
+    difficulty: String,
+    #[serde(default)]
+    nps: f32,
+    #[serde(default)]
+    notes: u32,
+}
+
+impl MapDetail {
+    /// The newest published version, falling back to the newest of any state.
+    fn latest_version(&self) -> Option<&MapVersion> {
+        let by_date = |a: &&MapVersion, b: &&MapVersion| a.created_at.cmp(&b.created_at);
+        self.versions
+            .iter()
+            .filter(|version| version.state == "Published")
+            .max_by(by_date)
+            .or_else(|| self.versions.iter().max_by(by_date))
+    }
+
+    fn into_song_info(self) -> Result<SongInfo, APIErr> {
+        let version = self.latest_version().ok_or_else(|| APIErr::SongNotFound {
+            key: self.id.clone(),
+        })?;
+        Ok(self.song_info(version))
+    }
+
+    fn song_info(&self, version: &MapVersion) -> SongInfo {
+        SongInfo {
+            id: self.id.clone(),
+            name: self.metadata.song_name.clone(),
+            author: self.metadata.level_author_name.clone(),
+            download_url: version.download_url.clone(),
+            hash: version.hash.clone(),
+            bpm: self.metadata.bpm,
+            duration: self.metadata.duration,
+            difficulties: version
+                .diffs
+                .iter()
+                .map(|diff| Difficulty {
+                    characteristic: diff.characteristic.clone(),
+                    difficulty: diff.difficulty.clone(),
+                    nps: diff.nps,
+                    notes: diff.notes,
+                })
+                .collect(),
+            uploader: self.uploader.name.clone(),
+            rating: self.stats.score,
+            upload_date: self.uploaded.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Looks up a song's latest version, falling back to what was last fetched for it when BeatSaver
+/// can't be reached.
+pub async fn get_song_info(id: String) -> Result<SongInfo, APIErr> {
+    match fetch_song_info(&id).await {
+        Ok(song_info) => {
+            cache::insert_song_infos([&song_info]);
+            save_cache().await;
+            Ok(song_info)
+        }
+        Err(err @ APIErr::SongNotFound { .. }) => Err(err),
+        Err(err) => cache::song_info(&id).ok_or(err),
+    }
+}
+
+async fn fetch_song_info(id: &str) -> Result<SongInfo, APIErr> {
     let addr = format!("{}/maps/id/{}", BSABER_ADDR, id);
-    let response = reqwest::get(addr).await?;
-    let contents = response.text().await.map_err(|_| APIErr::InvalidText)?;
-
-    // todo a closer look on this, not sure how the responses get formatted so I can't really
-    // modify this stuff without oversight or testing
-
-    // this may be able to take advantage of String::split
-    // there's a lot of unwrapping on network returns
-    let dl_start = contents.find("downloadURL").ok_or(APIErr::SongNotFound)?;
-    let dl_end = contents[dl_start..contents.len() - 1].find(',').unwrap() + dl_start;
-    let download_url = contents[dl_start + 15..dl_end - 1].to_string();
-
-    let name_start = contents.find("songName").unwrap();
-    let name_end = contents[name_start..contents.len() - 1].find(',').unwrap() + name_start;
-    let name = contents[name_start + 12..name_end - 1].to_string();
-
-    let author_start = contents.find("levelAuthorName").unwrap();
-    let author_end = contents[author_start..contents.len() - 1]
-        .find(',')
-        .unwrap()
-        + author_start;
-    let author = contents[author_start + 19..author_end - 7].to_string();
-
-    Ok(SongInfo {
-        id,
-        name,
-        download_url,
-        author,
-    })
-}
-
-async fn download_song(song_info: &SongInfo) -> Result<Vec<u8>, APIErr> {
-    let response = reqwest::get(&song_info.download_url).await?;
-    // the .bytes call is untested here, not sure if it converts the entire HTTP GET response into
-    // bytes or just the data we need here
-    let mut bytes_in = &response.bytes().await?.to_vec()[..];
-    let mut bytes_out = Vec::new();
-    tokio::io::copy(&mut bytes_in, &mut bytes_out).await?;
-    Ok(bytes_out)
-}
-
-fn unzip_song(song_info: SongInfo, bytes: Vec<u8>, dir: PathBuf) -> Result<(), APIErr> {
-    let song_path = dir.clone().join(PathBuf::from(song_info.to_string()));
-    std::fs::create_dir(song_path.clone())?;
-    let mut zip = ZipArchive::new(Cursor::new(bytes))?;
-    zip.extract(song_path.clone())?;
+    let response = http::send(|client| client.get(&addr)).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(APIErr::SongNotFound {
+            key: id.to_string(),
+        });
+    }
+    let contents = response.error_for_status()?.text().await?;
+
+    let map: MapDetail =
+        serde_json::from_str(&contents).map_err(|err| APIErr::invalid_response(&addr, err))?;
+    map.into_song_info()
+}
+
+async fn get_map_by_hash(hash: &str) -> Result<Option<MapDetail>, APIErr> {
+    let addr = format!("{}/maps/hash/{}", BSABER_ADDR, hash);
+    let response = http::send(|client| client.get(&addr)).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let contents = response.error_for_status()?.text().await?;
+    let map =
+        serde_json::from_str(&contents).map_err(|err| APIErr::invalid_response(&addr, err))?;
+    Ok(Some(map))
+}
+
+/// Looks up the version of a song with `hash`.
+/// Fails with `APIErr::VersionNotFound` if BeatSaver no longer hosts that exact version.
+pub async fn get_song_info_by_hash(hash: &str) -> Result<SongInfo, APIErr> {
+    get_song_infos_by_hash(&[hash.to_string()])
+        .await?
+        .remove(&hash.to_lowercase())
+        .ok_or_else(|| APIErr::VersionNotFound {
+            hash: hash.to_string(),
+        })
+}
+
+/// Looks up the versions with `hashes`, keyed by lowercase hash, asking for
+/// [`MAX_IDS_PER_REQUEST`] at a time. Versions BeatSaver no longer hosts are left out of the
+/// result.
+pub async fn get_song_infos_by_hash(
+    hashes: &[String],
+) -> Result<HashMap<String, SongInfo>, APIErr> {
+    let mut song_infos = HashMap::new();
+    for chunk in hashes.chunks(MAX_IDS_PER_REQUEST) {
+        for (hash, map) in get_maps_by_hash(chunk).await? {
+            let version = map
+                .versions
+                .iter()
+                .find(|version| version.hash.eq_ignore_ascii_case(&hash));
+            if let Some(version) = version {
+                song_infos.insert(hash, map.song_info(version));
+            }
+        }
+    }
+    Ok(song_infos)
+}
+
+/// Finds the keys of version hashes, keyed by lowercase hash, asking for
+/// [`MAX_IDS_PER_REQUEST`] at a time. Hashes BeatSaver doesn't know about are left out of the
+/// result.
+pub async fn get_keys_by_hash(hashes: &[String]) -> Result<HashMap<String, String>, APIErr> {
+    let mut keys = HashMap::new();
+    for chunk in hashes.chunks(MAX_IDS_PER_REQUEST) {
+        let maps = get_maps_by_hash(chunk).await?;
+        keys.extend(maps.into_iter().map(|(hash, map)| (hash, map.id)));
+    }
+    Ok(keys)
+}
+
+/// The maps with versions with `hashes`, at most [`MAX_IDS_PER_REQUEST`] of them, keyed by
+/// lowercase hash. Hashes BeatSaver doesn't know about are left out.
+async fn get_maps_by_hash(hashes: &[String]) -> Result<HashMap<String, MapDetail>, APIErr> {
+    match hashes {
+        [] => return Ok(HashMap::new()),
+        // like /maps/ids, a single hash answers with the map itself
+        [hash] => {
+            return Ok(get_map_by_hash(hash)
+                .await?
+                .map(|map| (hash.to_lowercase(), map))
+                .into_iter()
+                .collect())
+        }
+        _ => {}
+    }
+
+    let addr = format!("{}/maps/hash/{}", BSABER_ADDR, hashes.join(","));
+    let contents = http::send(|client| client.get(&addr))
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let maps: HashMap<String, Option<MapDetail>> =
+        serde_json::from_str(&contents).map_err(|err| APIErr::invalid_response(&addr, err))?;
+    Ok(maps
+        .into_iter()
+        .filter_map(|(hash, map)| Some((hash.to_lowercase(), map?)))
+        .collect())
+}
+
+/// Looks up songs by key, keyed by lowercase BeatSaver key, asking for [`MAX_IDS_PER_REQUEST`]
+/// at a time. Songs BeatSaver doesn't know about are left out of the result. What's found is
+/// added to the cache, but not written, see [`cache::save`]. When BeatSaver can't be reached, a
+/// batch whose songs are all cached is answered from the cache.
+pub async fn get_song_infos(ids: &[String]) -> Result<HashMap<String, SongInfo>, APIErr> {
+    let mut song_infos = HashMap::new();
+    for chunk in ids.chunks(MAX_IDS_PER_REQUEST) {
+        song_infos.extend(get_song_infos_chunk(chunk).await?);
+    }
+    Ok(song_infos)
+}
+
+async fn get_song_infos_chunk(ids: &[String]) -> Result<HashMap<String, SongInfo>, APIErr> {
+    match ids {
+        [] => return Ok(HashMap::new()),
+        // asking for a single key answers with the map itself rather than a map of keys
+        [id] => {
+            return match get_song_info(id.clone()).await {
+                Ok(song_info) => Ok(HashMap::from([(song_info.id.to_lowercase(), song_info)])),
+                Err(APIErr::SongNotFound { .. }) => Ok(HashMap::new()),
+                Err(err) => Err(err),
+            }
+        }
+        _ => {}
+    }
+
+    match fetch_song_infos(ids).await {
+        Ok(song_infos) => {
+            cache::insert_song_infos(song_infos.values());
+            Ok(song_infos)
+        }
+        // like a single key, only good enough if we've seen every song before
+        Err(err) => ids
+            .iter()
+            .map(|id| Some((id.to_lowercase(), cache::song_info(id)?)))
+            .collect::<Option<_>>()
+            .ok_or(err),
+    }
+}
+
+async fn fetch_song_infos(ids: &[String]) -> Result<HashMap<String, SongInfo>, APIErr> {
+    let addr = format!("{}/maps/ids/{}", BSABER_ADDR, ids.join(","));
+    let contents = http::send(|client| client.get(&addr))
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let maps: HashMap<String, Option<MapDetail>> =
+        serde_json::from_str(&contents).map_err(|err| APIErr::invalid_response(&addr, err))?;
+    let song_infos: HashMap<String, SongInfo> = maps
+        .into_values()
+        .flatten()
+        .filter_map(|map| map.into_song_info().ok())
+        .map(|song_info| (song_info.id.to_lowercase(), song_info))
+        .collect();
+    Ok(song_infos)
+}
+
+/// The size of a song's zip in bytes, `None` if BeatSaver doesn't say.
+pub async fn get_download_size(song_info: &SongInfo) -> Result<Option<u64>, APIErr> {
+    let response = http::send(|client| client.head(&song_info.download_url))
+        .await?
+        .error_for_status()?;
+    Ok(response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse().ok()))
+}
+
+/// `on_progress` is called with the bytes received so far and the size of the zip, if known.
+async fn download_song(
+    song_info: &SongInfo,
+    zip_path: &Path,
+    on_progress: &(impl Fn(u64, Option<u64>) + Send + Sync),
+) -> Result<(), APIErr> {
+    let mut response = http::send_download(|client| client.get(&song_info.download_url))
+        .await?
+        .error_for_status()?;
+    let total = response.content_length();
+    let mut received = 0;
+    on_progress(received, total);
+    let mut file = tokio::fs::File::create(zip_path)
+        .await
+        .with_path(zip_path)?;
+    while let Some(chunk) = http::read_chunk(&mut response).await? {
+        file.write_all(chunk.as_ref()).await.with_path(zip_path)?;
+        received += chunk.as_ref().len() as u64;
+        on_progress(received, total);
+    }
+    file.flush().await.with_path(zip_path)?;
     Ok(())
 }
 
-pub(in crate::api) async fn download_and_unzip_song(
+/// Extracts into the staging folder and only moves the song into `dir` once everything is there
+/// and matches the song's hash, so a broken or tampered zip never leaves a song behind.
+/// `replaces` is removed once the song is in place. Maps in a format that can't be checked are
+/// still installed, with a warning. Nothing is moved into `dir` once `is_cancelled` returns true.
+fn unzip_song(
+    staged_path: &Path,
+    zip_path: &Path,
+    song_path: &Path,
+    song_info: &SongInfo,
+    replaces: Option<&Path>,
+    is_cancelled: impl Fn() -> bool,
+) -> Result<Option<DownloadWarning>, APIErr> {
+    let result = (|| {
+        if staged_path.exists() {
+            std::fs::remove_dir_all(staged_path).with_path(staged_path)?;
+        }
+        std::fs::create_dir(staged_path).with_path(staged_path)?;
+        archive::extract_map(zip_path, staged_path)?;
+        if map_hash::find_info_dat(staged_path)
+            .with_path(staged_path)?
+            .is_none()
+        {
+            return Err(APIErr::NotAMap {
+                key: song_info.id.clone(),
+            });
+        }
+        let warning = match map_hash::compute_map_hash(staged_path).with_path(staged_path)? {
+            Some(hash) if !hash.eq_ignore_ascii_case(&song_info.hash) => {
+                return Err(APIErr::HashMismatch {
+                    key: song_info.id.clone(),
+                    expected: song_info.hash.clone(),
+                    actual: hash,
+                });
+            }
+            Some(_) => None,
+            None => Some(DownloadWarning::Unverified),
+        };
+        // aborting the download doesn't stop this thread, so check before touching `dir`
+        if is_cancelled() {
+            return Err(APIErr::Cancelled);
+        }
+        match replaces {
+            // the new version wants the old one's folder, move the old one aside until it's in
+            Some(old_path) if old_path == song_path => {
+                let backup_path = staged_path.with_extension("old");
+                std::fs::rename(old_path, &backup_path).with_path(old_path)?;
+                if let Err(err) = std::fs::rename(staged_path, song_path) {
+                    let _ = std::fs::rename(&backup_path, old_path);
+                    return Err(err).with_path(song_path);
+                }
+                let _ = std::fs::remove_dir_all(backup_path);
+            }
+            // a rename within the same drive is atomic
+            Some(old_path) => {
+                std::fs::rename(staged_path, song_path).with_path(song_path)?;
+                let _ = std::fs::remove_dir_all(old_path);
+            }
+            None => std::fs::rename(staged_path, song_path).with_path(song_path)?,
+        }
+        Ok(warning)
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(staged_path);
+    }
+    result
+}
+
+/// Downloads a song to a zip in the staging folder and extracts it from there, so only one chunk
+/// of it is ever held in memory. The song's folder in `dir` is named by `folder_template`.
+/// `replaces` is the folder of an installed version to remove once the song is in.
+/// `on_progress` is called with the bytes downloaded so far and the size of the zip, if known.
+/// `is_cancelled` is checked before the song is moved in, see [`APIErr::Cancelled`].
+/// Returns a warning if the song was installed without being checked against its hash.
+pub async fn download_and_unzip_song(
     song_info: SongInfo,
     dir: PathBuf,
-) -> Result<(), APIErr> {
-    let bytes = download_song(&song_info).await?;
-    // if unzip fails, look at download_song for a note
-    unzip_song(song_info, bytes, dir)?;
-    Ok(())
+    folder_template: &FolderTemplate,
+    replaces: Option<PathBuf>,
+    on_progress: impl Fn(u64, Option<u64>) + Send + Sync,
+    is_cancelled: impl Fn() -> bool + Send + 'static,
+) -> Result<Option<DownloadWarning>, APIErr> {
+    let staging = dir.join(STAGING_DIR);
+    tokio::fs::create_dir_all(&staging)
+        .await
+        .with_path(&staging)?;
+    // keys are plain hex, but they come from the network so don't trust them with a path either
+    let staged_name = sanitize_folder_name(&song_info.id);
+    let zip_path = staging.join(format!("{}.zip", staged_name));
+    let staged_path = staging.join(staged_name);
+    let song_path = dir.join(folder_template.folder_name(&song_info));
+
+    // the zip is only needed until it's extracted, whether that worked or not
+    let _zip_guard = RemoveOnDrop(zip_path.clone());
+    let result = async {
+        download_song(&song_info, &zip_path, &on_progress).await?;
+        let unzip_zip_path = zip_path.clone();
+        tokio::task::spawn_blocking(move || {
+            unzip_song(
+                &staged_path,
+                &unzip_zip_path,
+                &song_path,
+                &song_info,
+                replaces.as_deref(),
+                is_cancelled,
+            )
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .with_path(&zip_path)?
+    }
+    .await;
+
+    result
+}
+
+/// Removes a file when dropped, so it's cleaned up even when a download is cancelled mid way.
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `/maps/id` response, trimmed down but otherwise as BeatSaver sends it. Fields are in a
+    /// different order to the structs, and there are fields we don't read.
+    const MAP_DETAIL: &str = r#"{
+        "versions": [
+            {
+                "downloadURL": "https://r2cdn.beatsaver.com/old.zip",
+                "state": "Published",
+                "hash": "0000000000000000000000000000000000000001",
+                "createdAt": "2021-05-01T12:00:00Z",
+                "diffs": []
+            },
+            {
+                "createdAt": "2021-06-01T12:00:00Z",
+                "hash": "ABCDEF0000000000000000000000000000000002",
+                "state": "Published",
+                "coverURL": "https://cdn.beatsaver.com/cover.jpg",
+                "diffs": [
+                    {
+                        "njs": 16,
+                        "notes": 812,
+                        "characteristic": "Standard",
+                        "nps": 4.25,
+                        "difficulty": "ExpertPlus",
+                        "chroma": false
+                    },
+                    { "difficulty": "Easy", "characteristic": "OneSaber" }
+                ],
+                "downloadURL": "https://r2cdn.beatsaver.com/new.zip"
+            },
+            {
+                "state": "Testing",
+                "hash": "0000000000000000000000000000000000000003",
+                "createdAt": "2021-07-01T12:00:00Z",
+                "downloadURL": "https://r2cdn.beatsaver.com/testing.zip"
+            }
+        ],
+        "stats": { "upvotes": 10, "score": 0.875, "downvotes": 1 },
+        "name": "A \"quoted\" title, with {braces}",
+        "uploaded": "2021-05-01T12:00:00Z",
+        "metadata": {
+            "songAuthorName": "Artist",
+            "levelAuthorName": "Mapper \"Nick\"",
+            "duration": 201,
+            "songName": "Song \"Quoted\": Remix",
+            "bpm": 128.5
+        },
+        "automapper": false,
+        "uploader": { "name": "Uploader", "id": 42 },
+        "id": "1a2b"
+    }"#;
+
+    #[test]
+    fn parses_a_map() {
+        let map: MapDetail = serde_json::from_str(MAP_DETAIL).unwrap();
+        let song_info = map.into_song_info().unwrap();
+        assert_eq!(song_info.id, "1a2b");
+        assert_eq!(song_info.name, "Song \"Quoted\": Remix");
+        assert_eq!(song_info.author, "Mapper \"Nick\"");
+        assert_eq!(song_info.uploader, "Uploader");
+        assert_eq!(song_info.bpm, 128.5);
+        assert_eq!(song_info.duration, 201);
+        assert_eq!(song_info.rating, 0.875);
+        assert_eq!(song_info.upload_date, "2021-05-01T12:00:00Z");
+
+        // the newest published version, not the newer one that's still being tested
+        assert_eq!(song_info.hash, "ABCDEF0000000000000000000000000000000002");
+        assert_eq!(
+            song_info.download_url,
+            "https://r2cdn.beatsaver.com/new.zip"
+        );
+        assert_eq!(song_info.difficulties.len(), 2);
+        assert_eq!(song_info.difficulties[0].difficulty, "ExpertPlus");
+        assert_eq!(song_info.difficulties[0].notes, 812);
+        assert_eq!(song_info.difficulties[0].nps, 4.25);
+        assert_eq!(song_info.difficulties[1].characteristic, "OneSaber");
+        assert_eq!(song_info.difficulties[1].notes, 0);
+    }
+
+    #[test]
+    fn parses_a_batch_of_maps() {
+        // `/maps/ids` answers with every key asked for, `null` for the ones it doesn't have
+        let contents = format!(r#"{{ "1a2b": {}, "ffff": null }}"#, MAP_DETAIL);
+        let maps: HashMap<String, Option<MapDetail>> = serde_json::from_str(&contents).unwrap();
+        assert_eq!(maps.len(), 2);
+        assert!(maps["ffff"].is_none());
+        assert_eq!(maps["1a2b"].as_ref().unwrap().id, "1a2b");
+    }
+
+    #[test]
+    fn a_map_without_versions_isnt_found() {
+        let map: MapDetail = serde_json::from_str(
+            r#"{
+                "id": "1a2b",
+                "uploader": { "name": "Uploader" },
+                "metadata": {
+                    "bpm": 120,
+                    "duration": 0,
+                    "songName": "Song",
+                    "levelAuthorName": "Mapper"
+                },
+                "stats": {}
+            }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            map.into_song_info(),
+            Err(APIErr::SongNotFound { key }) if key == "1a2b"
+        ));
+    }
 }
@@ -1,67 +1,222 @@
 use crate::api::*;
-use crate::util::StringUtils;
+use futures::future::BoxFuture;
+use reqwest::header::{ETAG, IF_MATCH};
+use reqwest::StatusCode;
 
 const DB_ADDR: &str = "https://beat-sharer-default-rtdb.firebaseio.com";
+/// How many times allocation re-reads the index after losing a race to another uploader.
+const MAX_INDEX_ATTEMPTS: usize = 32;
 
-lazy_static! {
-    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+/// A Firebase realtime database, talked to over its REST protocol.
+///
+/// A list is stored at `<id>` as a `"key,key,...,"` string, the way beat-sharer has always stored
+/// them, so older versions can still download it. They'd ask BeatSaver for `key:hash` as if it
+/// were a key, so the versions songs are pinned to are kept apart at `<id>_pins`, as a
+/// `"key:hash,key:hash,...,"` string of just the pinned songs.
+pub struct FirebaseStore {
+    addr: String,
+    auth: String,
 }
 
-pub(in crate::api) async fn get_list(index: u8) -> Result<Vec<String>, APIErr> {
-    let auth = dotenv!("secret");
-    let addr = format!("{}/{}.json?auth={}", DB_ADDR, index, auth);
-    let response = reqwest::get(addr).await?;
-    // can't use implicit `?` From here since that would just map to APIErr::ReqwestFailed
-    let mut contents = response.text().await.map_err(|_| APIErr::InvalidText)?;
+impl FirebaseStore {
+    pub fn new(addr: String, auth: String) -> Self {
+        Self {
+            addr: addr.trim_end_matches('/').to_string(),
+            auth,
+        }
+    }
+
+    /// The public beat-sharer database.
+    pub fn public(auth: String) -> Self {
+        Self::new(DB_ADDR.to_string(), auth)
+    }
+
+    fn url(&self, path: &str) -> String {
+        if self.auth.is_empty() {
+            format!("{}/{}.json", self.addr, path)
+        } else {
+            format!("{}/{}.json?auth={}", self.addr, path, self.auth)
+        }
+    }
+
+    async fn get_list(&self, index: u32) -> Result<Vec<SharedSong>, APIErr> {
+        let url = self.url(&index.to_string());
+        let contents = http::send(|client| client.get(&url))
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        if contents == "null" {
+            return Err(APIErr::IndexNotFound { index });
+        }
+
+        let mut list = parse_songs(&url, &contents)?;
+
+        // lists uploaded before songs were pinned don't have any
+        let url = self.url(&pins_path(index));
+        let contents = http::send(|client| client.get(&url))
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        if contents != "null" {
+            let pins: HashMap<String, Option<String>> = parse_songs(&url, &contents)?
+                .into_iter()
+                .map(|song| (song.key.to_lowercase(), song.hash))
+                .collect();
+            for song in list.iter_mut().filter(|song| song.hash.is_none()) {
+                song.hash = pins.get(&song.key.to_lowercase()).cloned().flatten();
+            }
+        }
+        Ok(list)
+    }
+
+    /// Writes a list to an empty slot. Firebase rejects the write if anything has been stored at
+    /// `index` since we looked, so an existing list is never overwritten.
+    async fn put_list(&self, index: u32, list: Vec<SharedSong>) -> Result<(), APIErr> {
+        let mut keys = String::new();
+        let mut pins = String::new();
+        for song in &list {
+            keys.push_str(&format!("{},", song.key));
+            if song.hash.is_some() {
+                pins.push_str(&format!("{},", song));
+            }
+        }
+
+        // pins first, so the list is never there without them
+        if !pins.is_empty() {
+            self.put_new(index, &pins_path(index), &pins).await?;
+        }
+        self.put_new(index, &index.to_string(), &keys).await
+    }
+
+    async fn delete_list(&self, index: u32) -> Result<(), APIErr> {
+        for path in [index.to_string(), pins_path(index)] {
+            let url = self.url(&path);
+            http::send(|client| client.delete(&url))
+                .await?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+
+    /// Writes `value` to `path`, part of list `index`, only if nothing is stored there yet.
+    async fn put_new(&self, index: u32, path: &str, value: &str) -> Result<(), APIErr> {
+        let (contents, etag) = self.get_with_etag(path).await?;
+        if contents != "null" {
+            // an earlier try may have gone through without us hearing back
+            if serde_json::from_str::<String>(&contents).is_ok_and(|stored| stored == value) {
+                return Ok(());
+            }
+            return Err(APIErr::ListAlreadyExists { index });
+        }
+        if !self.put_if_match(path, value, &etag).await? {
+            return Err(APIErr::ListAlreadyExists { index });
+        }
+        Ok(())
+    }
 
-    if contents == "null" {
-        return Err(APIErr::IndexNotFound);
+    /// Reads the value stored at `path` along with the ETag Firebase uses for conditional writes.
+    async fn get_with_etag(&self, path: &str) -> Result<(String, String), APIErr> {
+        let url = self.url(path);
+        let response = http::send(|client| client.get(&url).header("X-Firebase-ETag", "true"))
+            .await?
+            .error_for_status()?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(String::from)
+            .ok_or_else(|| APIErr::invalid_response(&url, "no ETag"))?;
+        let contents = response.text().await?;
+        Ok((contents, etag))
     }
 
-    contents = contents.substring(1, contents.chars().count() - 2);
-    let mut list = Vec::new();
-    for code in contents.split(',') {
-        list.push(String::from(code));
+    /// Writes `value` to `path` only if it still matches `etag`.
+    /// Returns `false` when another client wrote to `path` first. Failures aren't retried, see
+    /// `http::send_once`.
+    async fn put_if_match<T: serde::Serialize + ?Sized>(
+        &self,
+        path: &str,
+        value: &T,
+        etag: &str,
+    ) -> Result<bool, APIErr> {
+        let url = self.url(path);
+        let response =
+            http::send_once(|client| client.put(&url).header(IF_MATCH, etag).json(value)).await?;
+        if response.status() == StatusCode::PRECONDITION_FAILED {
+            return Ok(false);
+        }
+        response.error_for_status()?;
+        Ok(true)
     }
 
-    Ok(list)
+    /// Claims the next free ID by compare-and-swapping the shared counter.
+    ///
+    /// Slots that already hold a list (left over from when the counter wrapped around) are
+    /// skipped, so the returned ID is always empty and owned by the caller.
+    async fn get_and_inc_index(&self) -> Result<u32, APIErr> {
+        let mut attempts = 0;
+        while attempts < MAX_INDEX_ATTEMPTS {
+            let (contents, etag) = self.get_with_etag("index").await?;
+            let index = parse_index(&contents)
+                .map_err(|err| APIErr::invalid_response(&self.url("index"), err))?;
+            let next = index.checked_add(1).ok_or(APIErr::IndexExhausted)?;
+
+            if !self.put_if_match("index", &next.to_string(), &etag).await? {
+                // someone else claimed this index first, try again with the new value
+                attempts += 1;
+                continue;
+            }
+
+            let (list, _) = self.get_with_etag(&index.to_string()).await?;
+            if list == "null" {
+                return Ok(index);
+            }
+        }
+        Err(APIErr::IndexContention)
+    }
 }
 
-pub(in crate::api) async fn put_list(index: u8, list: String) -> Result<(), APIErr> {
-    CLIENT
-        .put(format!(
-            "{}/{}.json?auth={}",
-            DB_ADDR,
-            index,
-            dotenv!("secret")
-        ))
-        .json(&list)
-        .send()
-        .await?;
-    Ok(())
+impl ListStore for FirebaseStore {
+    fn allocate(&self) -> BoxFuture<'_, Result<u32, APIErr>> {
+        Box::pin(self.get_and_inc_index())
+    }
+
+    fn get(&self, index: u32) -> BoxFuture<'_, Result<Vec<SharedSong>, APIErr>> {
+        Box::pin(self.get_list(index))
+    }
+
+    fn put(&self, index: u32, list: Vec<SharedSong>) -> BoxFuture<'_, Result<(), APIErr>> {
+        Box::pin(self.put_list(index, list))
+    }
+
+    fn delete(&self, index: u32) -> BoxFuture<'_, Result<(), APIErr>> {
+        Box::pin(self.delete_list(index))
+    }
 }
 
-async fn get_index() -> Result<u8, APIErr> {
-    let auth = dotenv!("secret");
-    let addr = format!("{}/index.json?auth={}", DB_ADDR, auth);
-    let response = reqwest::get(addr).await?;
-    // can't use From here since that would just map to APIErr::ReqwestFailed
-    let mut contents = response.text().await.map_err(|_| APIErr::InvalidText)?;
-    contents = contents.substring(1, contents.len() - 1);
-    Ok(contents.parse::<u8>().unwrap())
+fn pins_path(index: u32) -> String {
+    format!("{}_pins", index)
 }
 
-async fn put_index(index: u8) -> Result<(), APIErr> {
-    CLIENT
-        .put(format!("{}/index.json?auth={}", DB_ADDR, dotenv!("secret")))
-        .json(&index.to_string())
-        .send()
-        .await?;
-    Ok(())
+/// Parses a stored `"key,key:hash,...,"` string.
+fn parse_songs(url: &str, contents: &str) -> Result<Vec<SharedSong>, APIErr> {
+    let contents: String = serde_json::from_str(contents)
+        .map_err(|err| APIErr::invalid_response(url, format!("not a list: {}", err)))?;
+    // every song ends with a comma
+    Ok(contents
+        .split(',')
+        .filter(|song| !song.is_empty())
+        .map(SharedSong::parse)
+        .collect())
 }
 
-pub(in crate::api) async fn get_and_inc_index() -> Result<u8, APIErr> {
-    let index = get_index().await?;
-    put_index(index.wrapping_add(1)).await?;
-    Ok(index)
+fn parse_index(contents: &str) -> Result<u32, std::num::ParseIntError> {
+    // older clients stored the index as a JSON string, an empty database has no index at all
+    match contents.trim_matches('"') {
+        "null" => Ok(0),
+        index => index.parse::<u32>(),
+    }
 }
@@ -0,0 +1,185 @@
+use crate::api::*;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::time::Duration;
+
+/// Settings for the HTTP client every request in `api` goes through.
+#[derive(Clone, Debug)]
+pub struct HttpConfig {
+    /// How long to wait for a connection to be established.
+    pub connect_timeout: Duration,
+    /// How long a whole API request (metadata, lists) may take.
+    pub request_timeout: Duration,
+    /// How long a download may go without receiving any data.
+    pub read_timeout: Duration,
+    /// Retries after the first attempt for connection failures, timeouts, 429s and 5xx responses.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every retry after that.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Upper bound on how long a `Retry-After` header can make us wait.
+    pub max_retry_after: Duration,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(30),
+            max_retries: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_retry_after: Duration::from_secs(120),
+        }
+    }
+}
+
+struct HttpClient {
+    client: reqwest::Client,
+    config: HttpConfig,
+}
+
+impl HttpClient {
+    fn new(config: HttpConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("beat-sharer/", env!("CARGO_PKG_VERSION")))
+            .connect_timeout(config.connect_timeout)
+            .pool_idle_timeout(Duration::from_secs(90))
+            .tcp_keepalive(Duration::from_secs(60))
+            .build()
+            .expect("failed to initialize the HTTP client");
+        Self { client, config }
+    }
+}
+
+lazy_static! {
+    static ref CLIENT: RwLock<Arc<HttpClient>> =
+        RwLock::new(Arc::new(HttpClient::new(HttpConfig::default())));
+}
+
+/// Replaces the shared client. Requests already running finish with the old settings.
+pub fn configure_http(config: HttpConfig) {
+    *CLIENT.write().expect(POISONED_LOCK_MESSAGE) = Arc::new(HttpClient::new(config));
+}
+
+fn client() -> Arc<HttpClient> {
+    CLIENT.read().expect(POISONED_LOCK_MESSAGE).clone()
+}
+
+/// Sends an API request built by `build`, retrying transient failures.
+///
+/// Whatever response comes back last is returned as is, callers still need to check its status.
+pub(in crate::api) async fn send(
+    build: impl Fn(&reqwest::Client) -> RequestBuilder,
+) -> Result<Response, APIErr> {
+    let http = client();
+    let timeout = http.config.request_timeout;
+    send_with_retries(&http, |client| build(client).timeout(timeout), None).await
+}
+
+/// Like `send`, but never retried, for writes that mustn't be sent twice. A conditional write
+/// that went through without us hearing back would fail its retry as if someone else had written.
+pub(in crate::api) async fn send_once(
+    build: impl Fn(&reqwest::Client) -> RequestBuilder,
+) -> Result<Response, APIErr> {
+    let http = client();
+    let request = build(&http.client)
+        .timeout(http.config.request_timeout)
+        .build()?;
+    Ok(http.client.execute(request).await?)
+}
+
+/// Like `send`, but without the request timeout so large bodies can take as long as they need.
+/// Waiting for the response headers is limited by the read timeout instead, read the body with
+/// `read_chunk` to keep it limited while downloading.
+pub(in crate::api) async fn send_download(
+    build: impl Fn(&reqwest::Client) -> RequestBuilder,
+) -> Result<Response, APIErr> {
+    let http = client();
+    let read_timeout = http.config.read_timeout;
+    send_with_retries(&http, build, Some(read_timeout)).await
+}
+
+/// Reads the next piece of a download's body, failing if nothing arrives within the read timeout.
+pub(in crate::api) async fn read_chunk(
+    response: &mut Response,
+) -> Result<Option<impl AsRef<[u8]>>, APIErr> {
+    let read_timeout = client().config.read_timeout;
+    match tokio::time::timeout(read_timeout, response.chunk()).await {
+        Ok(chunk) => Ok(chunk?),
+        Err(_) => Err(APIErr::Timeout {
+            url: Some(redact_url(response.url().as_str())),
+        }),
+    }
+}
+
+async fn send_with_retries(
+    http: &HttpClient,
+    build: impl Fn(&reqwest::Client) -> RequestBuilder,
+    header_timeout: Option<Duration>,
+) -> Result<Response, APIErr> {
+    let config = &http.config;
+    let mut attempt = 0;
+    let mut url;
+    let result = loop {
+        let request = build(&http.client).build()?;
+        url = Some(redact_url(request.url().as_str()));
+        let request = http.client.execute(request);
+        // Err(None) means we gave up waiting for the headers ourselves
+        let result = match header_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, request).await {
+                Ok(result) => result.map_err(Some),
+                Err(_) => Err(None),
+            },
+            None => request.await.map_err(Some),
+        };
+
+        let wait = match &result {
+            Ok(response) if is_retryable_status(response.status()) => {
+                retry_after(response, config).unwrap_or_else(|| backoff(config, attempt))
+            }
+            Err(None) => backoff(config, attempt),
+            Err(Some(err)) if err.is_timeout() || err.is_connect() => backoff(config, attempt),
+            _ => break result,
+        };
+
+        if attempt >= config.max_retries {
+            break result;
+        }
+        attempt += 1;
+        tokio::time::sleep(wait).await;
+    };
+
+    match result {
+        Ok(response) => Ok(response),
+        Err(Some(err)) => Err(err.into()),
+        Err(None) => Err(APIErr::Timeout { url }),
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+}
+
+fn backoff(config: &HttpConfig, attempt: u32) -> Duration {
+    config
+        .initial_backoff
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(config.max_backoff)
+}
+
+/// The wait a 429 or 503 asked for, only the delay-seconds form is understood.
+fn retry_after(response: &Response, config: &HttpConfig) -> Option<Duration> {
+    let seconds = response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(Duration::from_secs(seconds).min(config.max_retry_after))
+}
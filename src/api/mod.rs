@@ -1,21 +1,39 @@
+use crate::library::{Library, LocalSong};
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io;
 use std::num::NonZeroUsize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 use zip::result::ZipError;
 
-mod beatsaver;
+mod archive;
+pub mod beatsaver;
 mod db;
+mod http;
+mod naming;
+mod plan;
+mod report;
+mod store;
+
+pub use db::FirebaseStore;
+pub use http::{configure_http, HttpConfig};
+pub use naming::FolderTemplate;
+pub use plan::{DownloadPlan, PlanStatus, PlannedSong};
+pub use report::{DownloadReport, FailedSong, WarnedSong};
+pub use store::{DirectoryStore, ListStore, StoreConfig};
 
 const SEND_UNWRAP_FAILURE_MESSAGE: &str =
     "failed to send resulting value, was the receiver dropped?";
-const POISONED_MUTEX_MESSAGE: &str =
+pub(crate) const POISONED_MUTEX_MESSAGE: &str =
     "failed to unlock mutex due to another thread panicking while holding it";
+const POISONED_LOCK_MESSAGE: &str =
+    "failed to acquire lock due to another thread panicking while holding it";
 
 lazy_static! {
     static ref ASYNC_RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_multi_thread()
@@ -23,102 +41,568 @@ lazy_static! {
         .enable_time()
         .build()
         .unwrap();
+    static ref STORE: RwLock<Arc<dyn ListStore>> = RwLock::new(StoreConfig::default().build());
+    /// What `StoreConfig::Public` authenticates with, see [`set_public_auth`].
+    static ref PUBLIC_AUTH: RwLock<String> = Default::default();
+    /// Lowercase version hash to BeatSaver key, `None` for hashes BeatSaver doesn't know.
+    static ref KEY_CACHE: Mutex<HashMap<String, Option<String>>> = Default::default();
+}
+
+/// Sets the auth token `StoreConfig::Public` sends to the public database. The beat-sharer
+/// executables set it to the secret they were built with, before any list operation.
+pub fn set_public_auth(auth: &str) {
+    *PUBLIC_AUTH.write().expect(POISONED_LOCK_MESSAGE) = auth.to_string();
+}
+
+fn public_auth() -> String {
+    PUBLIC_AUTH.read().expect(POISONED_LOCK_MESSAGE).clone()
+}
+
+/// Points every list operation at the store described by `config`.
+pub fn set_store(config: &StoreConfig) {
+    *STORE.write().expect(POISONED_LOCK_MESSAGE) = config.build();
+}
+
+fn store() -> Arc<dyn ListStore> {
+    STORE.read().expect(POISONED_LOCK_MESSAGE).clone()
 }
 
-pub fn get_list(index: u8) -> oneshot::Receiver<Result<Vec<String>, APIErr>> {
+pub fn get_list(index: u32) -> oneshot::Receiver<Result<Vec<SharedSong>, APIErr>> {
     let (sender, receiver) = oneshot::channel();
-    async fn f(sender: oneshot::Sender<Result<Vec<String>, APIErr>>, index: u8) {
-        let result = db::get_list(index).await;
+    async fn f(
+        sender: oneshot::Sender<Result<Vec<SharedSong>, APIErr>>,
+        store: Arc<dyn ListStore>,
+        index: u32,
+    ) {
+        let result = store.get(index).await;
         sender.send(result).expect(SEND_UNWRAP_FAILURE_MESSAGE);
     }
-    ASYNC_RUNTIME.spawn(f(sender, index));
+    ASYNC_RUNTIME.spawn(f(sender, store(), index));
     receiver
 }
 
-pub fn put_list(index: u8, list: String) -> oneshot::Receiver<Result<(), APIErr>> {
+pub fn put_list(index: u32, list: Vec<SharedSong>) -> oneshot::Receiver<Result<(), APIErr>> {
     let (sender, receiver) = oneshot::channel();
-    async fn f(sender: oneshot::Sender<Result<(), APIErr>>, index: u8, list: String) {
-        let result = db::put_list(index, list).await;
+    async fn f(
+        sender: oneshot::Sender<Result<(), APIErr>>,
+        store: Arc<dyn ListStore>,
+        index: u32,
+        list: Vec<SharedSong>,
+    ) {
+        let result = store.put(index, list).await;
         sender.send(result).expect(SEND_UNWRAP_FAILURE_MESSAGE);
     }
-    ASYNC_RUNTIME.spawn(f(sender, index, list));
+    ASYNC_RUNTIME.spawn(f(sender, store(), index, list));
     receiver
 }
 
-pub fn get_and_inc_index() -> oneshot::Receiver<Result<u8, APIErr>> {
+pub fn delete_list(index: u32) -> oneshot::Receiver<Result<(), APIErr>> {
     let (sender, receiver) = oneshot::channel();
-    async fn f(sender: oneshot::Sender<Result<u8, APIErr>>) {
-        let result = db::get_and_inc_index().await;
+    async fn f(sender: oneshot::Sender<Result<(), APIErr>>, store: Arc<dyn ListStore>, index: u32) {
+        let result = store.delete(index).await;
         sender.send(result).expect(SEND_UNWRAP_FAILURE_MESSAGE);
     }
-    ASYNC_RUNTIME.spawn(f(sender));
+    ASYNC_RUNTIME.spawn(f(sender, store(), index));
+    receiver
+}
+
+pub fn get_and_inc_index() -> oneshot::Receiver<Result<u32, APIErr>> {
+    let (sender, receiver) = oneshot::channel();
+    async fn f(sender: oneshot::Sender<Result<u32, APIErr>>, store: Arc<dyn ListStore>) {
+        let result = store.allocate().await;
+        sender.send(result).expect(SEND_UNWRAP_FAILURE_MESSAGE);
+    }
+    ASYNC_RUNTIME.spawn(f(sender, store()));
+    receiver
+}
+
+/// Finds the BeatSaver keys of version hashes, keyed by lowercase hash. Hashes BeatSaver doesn't
+/// know are left out. Answers are remembered, so only new hashes are ever sent to BeatSaver.
+pub fn resolve_keys(
+    hashes: Vec<String>,
+) -> oneshot::Receiver<Result<HashMap<String, String>, APIErr>> {
+    let (sender, receiver) = oneshot::channel();
+    async fn f(
+        sender: oneshot::Sender<Result<HashMap<String, String>, APIErr>>,
+        hashes: Vec<String>,
+    ) {
+        let result = resolve_keys_async(hashes).await;
+        // nobody may be waiting anymore, e.g. the window closed, but the keys are cached anyway
+        let _ = sender.send(result);
+    }
+    ASYNC_RUNTIME.spawn(f(sender, hashes));
+    receiver
+}
+
+async fn resolve_keys_async(hashes: Vec<String>) -> Result<HashMap<String, String>, APIErr> {
+    let hashes: HashSet<String> = hashes.iter().map(|hash| hash.to_lowercase()).collect();
+    let missing: Vec<String> = {
+        let mut cache = KEY_CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+        // keys found in earlier runs, only hashes BeatSaver didn't know are asked about again
+        for hash in &hashes {
+            if !cache.contains_key(hash) {
+                if let Some(key) = crate::cache::key(hash) {
+                    cache.insert(hash.clone(), Some(key));
+                }
+            }
+        }
+        hashes
+            .iter()
+            .filter(|hash| !cache.contains_key(*hash))
+            .cloned()
+            .collect()
+    };
+
+    for chunk in missing.chunks(beatsaver::MAX_IDS_PER_REQUEST) {
+        let found = beatsaver::get_keys_by_hash(chunk).await?;
+        crate::cache::insert_keys(&found);
+        let mut cache = KEY_CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+        for hash in chunk {
+            cache.insert(hash.clone(), found.get(hash).cloned());
+        }
+    }
+    save_cache().await;
+
+    let cache = KEY_CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+    Ok(hashes
+        .into_iter()
+        .filter_map(|hash| {
+            let key = cache.get(&hash).cloned().flatten()?;
+            Some((hash, key))
+        })
+        .collect())
+}
+
+/// Reads every song in `paths` as one library, see [`Library::scan_all`].
+pub fn scan_library(paths: Vec<PathBuf>) -> oneshot::Receiver<io::Result<Library>> {
+    let (sender, receiver) = oneshot::channel();
+    ASYNC_RUNTIME.spawn_blocking(move || {
+        // a newer scan may have replaced this one
+        let _ = sender.send(Library::scan_all(&paths));
+    });
+    receiver
+}
+
+/// Reads changed song folders again, see [`Library::read_folders`].
+pub fn read_song_folders(folders: Vec<PathBuf>) -> oneshot::Receiver<Vec<LocalSong>> {
+    let (sender, receiver) = oneshot::channel();
+    ASYNC_RUNTIME.spawn_blocking(move || {
+        // the library may have been scanned again since
+        let _ = sender.send(Library::read_folders(&folders));
+    });
     receiver
 }
 
+/// Writes the cache without blocking the runtime. It's only a cache, failing to write it is fine.
+pub(crate) async fn save_cache() {
+    let _ = tokio::task::spawn_blocking(crate::cache::save).await;
+}
+
+/// Twice the available parallelism, downloads spend most of their time waiting on the network.
+pub fn default_max_concurrent_downloads() -> NonZeroUsize {
+    NonZeroUsize::new(
+        std::thread::available_parallelism()
+            .unwrap_or(NonZeroUsize::new(1).unwrap())
+            .get()
+            * 2,
+    )
+    .unwrap()
+}
+
+/// Removes whatever an interrupted download left in `dir`'s staging folder.
+/// Don't call this while a download into `dir` is running.
+pub fn clean_staging(dir: &Path) -> io::Result<()> {
+    match std::fs::remove_dir_all(dir.join(beatsaver::STAGING_DIR)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Downloads every song in `list` that isn't in `library`. Pinned songs are downloaded
+/// at their pinned version if BeatSaver still has it, otherwise at their latest version with a
+/// `DownloadWarning`.
 pub fn download(
-    id_list: Vec<String>,
-    local_list: Vec<String>,
+    list: Vec<SharedSong>,
+    library: Library,
+    dir: PathBuf,
+    folder_template: FolderTemplate,
+    max_concurrent_downloads: NonZeroUsize,
+) -> DownloadObserver {
+    let (updater, observer) = SharedInfo::create(max_concurrent_downloads);
+    updater.set_downloading(true);
+    ASYNC_RUNTIME.spawn(download_list_async(
+        list,
+        library,
+        dir,
+        folder_template,
+        updater,
+    ));
+    observer
+}
+
+/// Works out what downloading `list` would do, without downloading anything, so the songs to
+/// download can be picked with [`download_plan`].
+pub fn plan_download(
+    list: Vec<SharedSong>,
+    library: Library,
+) -> oneshot::Receiver<Result<DownloadPlan, APIErr>> {
+    let (sender, receiver) = oneshot::channel();
+    async fn f(
+        sender: oneshot::Sender<Result<DownloadPlan, APIErr>>,
+        list: Vec<SharedSong>,
+        library: Library,
+    ) {
+        let result = plan::plan_async(list, library).await;
+        sender.send(result).expect(SEND_UNWRAP_FAILURE_MESSAGE);
+    }
+    ASYNC_RUNTIME.spawn(f(sender, list, library));
+    receiver
+}
+
+/// Downloads the selected songs of a plan into `dir`. Outdated songs replace the version that's
+/// installed.
+pub fn download_plan(
+    plan: DownloadPlan,
     dir: PathBuf,
+    folder_template: FolderTemplate,
     max_concurrent_downloads: NonZeroUsize,
 ) -> DownloadObserver {
     let (updater, observer) = SharedInfo::create(max_concurrent_downloads);
     updater.set_downloading(true);
-    ASYNC_RUNTIME.spawn(download_list_async(id_list, local_list, dir, updater));
+    let mut songs = Vec::new();
+    for planned in plan.songs.into_iter().filter(|planned| planned.selected) {
+        if let Some(warning) = planned.warning {
+            updater.add_warning(planned.key.clone(), warning);
+        }
+        if let Some(song_info) = planned.song_info {
+            songs.push(QueuedSong {
+                song_info,
+                replaces: planned.replaces,
+                size: planned.size,
+            });
+        }
+    }
+    ASYNC_RUNTIME.spawn(download_songs_async(songs, dir, folder_template, updater));
     observer
 }
 
+/// Downloads the songs that failed in a finished download again, at the same versions and
+/// replacing the same installed songs.
+pub fn retry_failed(
+    failed: &DownloadObserver,
+    dir: PathBuf,
+    folder_template: FolderTemplate,
+    max_concurrent_downloads: NonZeroUsize,
+) -> DownloadObserver {
+    let (updater, observer) = SharedInfo::create(max_concurrent_downloads);
+    updater.set_downloading(true);
+    let keys: HashSet<String> = failed
+        .failed_downloads()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    for (key, warning) in failed.warnings() {
+        if keys.contains(&key) {
+            updater.add_warning(key, warning);
+        }
+    }
+    let info = &failed.info;
+    let songs: Vec<QueuedSong> = info
+        .queued
+        .lock()
+        .expect(POISONED_MUTEX_MESSAGE)
+        .iter()
+        .filter(|song| keys.contains(&song.song_info.id))
+        .cloned()
+        .collect();
+    let unresolved: Vec<SharedSong> = info
+        .unresolved
+        .lock()
+        .expect(POISONED_MUTEX_MESSAGE)
+        .iter()
+        .filter(|song| keys.contains(&song.key))
+        .cloned()
+        .collect();
+    ASYNC_RUNTIME.spawn(retry_async(
+        unresolved,
+        songs,
+        dir,
+        folder_template,
+        updater,
+    ));
+    observer
+}
+
+async fn retry_async(
+    unresolved: Vec<SharedSong>,
+    mut songs: Vec<QueuedSong>,
+    dir: PathBuf,
+    folder_template: FolderTemplate,
+    updater: DownloadUpdater,
+) {
+    // songs that couldn't be looked up the first time go after the rest
+    let resolved = resolve_song_infos(&unresolved, &updater).await;
+    songs.extend(resolved.into_iter().map(|song_info| QueuedSong {
+        song_info,
+        replaces: None,
+        size: None,
+    }));
+    download_songs_async(songs, dir, folder_template, updater).await;
+}
+
 async fn download_list_async(
-    mut id_list: Vec<String>,
-    local_list: Vec<String>,
+    mut list: Vec<SharedSong>,
+    library: Library,
     dir: PathBuf,
+    folder_template: FolderTemplate,
     updater: DownloadUpdater,
 ) {
-    let mut handles = FuturesUnordered::new();
+    // a song is already there if we have its key, or the exact version it's pinned to
+    let (keys, hashes) = (library.keys(), library.hashes());
+    list.retain(|song| {
+        !keys.contains(&song.key.to_lowercase())
+            && !song.hash.as_ref().is_some_and(|hash| hashes.contains(hash))
+    });
+
+    let songs = resolve_song_infos(&list, &updater).await;
+    let songs = songs
+        .into_iter()
+        .map(|song_info| QueuedSong {
+            song_info,
+            replaces: None,
+            size: None,
+        })
+        .collect();
+    download_songs_async(songs, dir, folder_template, updater).await;
+}
 
-    id_list.retain(|id| !local_list.contains(id));
+/// A looked up song waiting to be downloaded.
+#[derive(Clone)]
+struct QueuedSong {
+    song_info: SongInfo,
+    /// The installed version's folder, removed once this one is in.
+    replaces: Option<PathBuf>,
+    /// Size of the zip in bytes, if it's already known.
+    size: Option<u64>,
+}
 
-    while let Some(id) = id_list.pop() {
-        let handle = tokio::spawn(download_async(id.clone(), dir.clone()));
+async fn download_songs_async(
+    mut songs: Vec<QueuedSong>,
+    dir: PathBuf,
+    folder_template: FolderTemplate,
+    updater: DownloadUpdater,
+) {
+    let mut handles = FuturesUnordered::new();
+    updater.set_songs(
+        songs
+            .iter()
+            .map(|song| (song.song_info.clone(), song.size))
+            .collect(),
+    );
+    updater.set_queued(songs.clone());
+    // popped from the back, so reversed to download in list order
+    songs.reverse();
+
+    'songs: while let Some(song) = songs.pop() {
+        // songs already downloading still finish while paused, so keep collecting them
+        loop {
+            let resumed = Box::pin(updater.wait_while_paused());
+            if handles.is_empty() {
+                if !resumed.await {
+                    break 'songs;
+                }
+                break;
+            }
+            match futures::future::select(handles.next(), resumed).await {
+                futures::future::Either::Left((Some(result), _)) => {
+                    handle_result(&updater, result).await
+                }
+                futures::future::Either::Left((None, _)) => {}
+                futures::future::Either::Right((true, _)) => break,
+                futures::future::Either::Right((false, _)) => break 'songs,
+            }
+        }
+        let id = song.song_info.id.clone();
+        let handle = tokio::spawn(download_async(
+            song,
+            dir.clone(),
+            folder_template.clone(),
+            updater.clone(),
+        ));
         updater.add_ongoing_download(id);
         handles.push(handle);
 
         while handles.len() > updater.get_max_concurrent_downloads().get() {
-            if let Some(result) = handles.next().await {
-                handle_result(&updater, result).await;
+            let next = futures::future::select(handles.next(), Box::pin(updater.cancelled()));
+            match next.await {
+                futures::future::Either::Left((Some(result), _)) => {
+                    handle_result(&updater, result).await
+                }
+                _ => break,
             }
         }
     }
+
+    if updater.is_cancelled() {
+        // dropping a download removes its zip, a song being extracted is either finished or
+        // cleaned up by the extraction itself
+        for handle in handles.iter() {
+            handle.abort();
+        }
+    }
     handles
         .for_each(|result| handle_result(&updater, result))
         .await;
+    updater.cancel_unfinished();
+    save_cache().await;
     updater.set_downloading(false);
 }
 
+/// Looks up every song in as few BeatSaver requests as possible.
+/// Songs that can't be found are reported as failures and left out.
+async fn resolve_song_infos(list: &[SharedSong], updater: &DownloadUpdater) -> Vec<SongInfo> {
+    let list = unique_songs(list);
+    let mut songs = Vec::with_capacity(list.len());
+    for chunk in list.chunks(beatsaver::MAX_IDS_PER_REQUEST) {
+        let ids: Vec<String> = chunk.iter().map(|song| song.key.clone()).collect();
+        match beatsaver::get_song_infos(&ids).await {
+            Ok(mut infos) => {
+                let pinned = get_pinned_versions(chunk, &infos).await;
+                for song in chunk {
+                    match infos.remove(&song.key.to_lowercase()) {
+                        Some(latest) => match pick_version(song, latest, &pinned) {
+                            Ok((song_info, warning)) => {
+                                if let Some(warning) = warning {
+                                    updater.add_warning(song.key.clone(), warning);
+                                }
+                                songs.push(song_info);
+                            }
+                            Err(err) => {
+                                updater.add_failure(song.key.clone(), err).await;
+                                updater.add_unresolved(song.clone());
+                            }
+                        },
+                        None => {
+                            updater
+                                .add_failure(
+                                    song.key.clone(),
+                                    APIErr::SongNotFound {
+                                        key: song.key.clone(),
+                                    },
+                                )
+                                .await;
+                            updater.add_unresolved(song.clone());
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                for song in chunk {
+                    updater.add_failure(song.key.clone(), err.clone()).await;
+                    updater.add_unresolved(song.clone());
+                }
+            }
+        }
+    }
+    songs
+}
+
+/// `list` without repeats of a key, so a song listed twice is looked up and downloaded once.
+fn unique_songs(list: &[SharedSong]) -> Vec<SharedSong> {
+    let mut seen = HashSet::new();
+    list.iter()
+        .filter(|song| seen.insert(song.key.to_lowercase()))
+        .cloned()
+        .collect()
+}
+
+/// Looks up the versions `songs` are pinned to that aren't their latest version in `latest`, in
+/// as few requests as possible. Keyed by lowercase hash, versions BeatSaver no longer has are left
+/// out.
+async fn get_pinned_versions(
+    songs: &[SharedSong],
+    latest: &HashMap<String, SongInfo>,
+) -> Result<HashMap<String, SongInfo>, APIErr> {
+    let hashes: Vec<String> = songs
+        .iter()
+        .filter_map(|song| {
+            let latest = latest.get(&song.key.to_lowercase())?;
+            song.hash
+                .clone()
+                .filter(|hash| !hash.eq_ignore_ascii_case(&latest.hash))
+        })
+        .collect();
+    beatsaver::get_song_infos_by_hash(&hashes).await
+}
+
+/// Picks the version of `song` to download, given its latest version and what
+/// `get_pinned_versions` found. Falls back to the latest version only if BeatSaver says the
+/// pinned one is gone, not when it couldn't be asked.
+fn pick_version(
+    song: &SharedSong,
+    latest: SongInfo,
+    pinned: &Result<HashMap<String, SongInfo>, APIErr>,
+) -> Result<(SongInfo, Option<DownloadWarning>), APIErr> {
+    let hash = match &song.hash {
+        Some(hash) if !hash.eq_ignore_ascii_case(&latest.hash) => hash.to_lowercase(),
+        _ => return Ok((latest, None)),
+    };
+    match pinned {
+        Ok(pinned) => match pinned.get(&hash) {
+            Some(song_info) => Ok((song_info.clone(), None)),
+            None => Ok((latest, Some(DownloadWarning::PinnedVersionUnavailable))),
+        },
+        Err(err) => Err(err.clone()),
+    }
+}
+
 async fn handle_result(
     updater: &DownloadUpdater,
-    result: Result<(String, Result<(), APIErr>), tokio::task::JoinError>,
+    result: Result<(String, Result<Option<DownloadWarning>, APIErr>), tokio::task::JoinError>,
 ) {
     match result {
-        Ok((id, Ok(_))) => {
+        Ok((id, Ok(warning))) => {
+            if let Some(warning) = warning {
+                updater.add_warning(id.clone(), warning);
+            }
             updater.increment_downloaded().await;
+            updater.set_song_state(&id, SongState::Done);
             updater.remove_ongoing_download(id);
         }
+        // marked as cancelled with the rest once everything has stopped
+        Ok((id, Err(APIErr::Cancelled))) => updater.remove_ongoing_download(id),
         Ok((id, Err(err))) => {
             updater.add_failure(id.clone(), err).await;
+            updater.set_song_state(&id, SongState::Failed);
             updater.remove_ongoing_download(id);
         }
+        // cancelled, the song is marked as such once everything has stopped
+        Err(err) if err.is_cancelled() => {}
         Err(err) => panic!("error joining with download task: {}", err),
     }
 }
 
-async fn download_async(id: String, dir: PathBuf) -> (String, Result<(), APIErr>) {
-    (id.clone(), download_async_inner(id, dir).await)
-}
-
-async fn download_async_inner(id: String, dir: PathBuf) -> Result<(), APIErr> {
-    let song_info = beatsaver::get_song_info(id).await?;
-    beatsaver::download_and_unzip_song(song_info, dir).await?;
-    Ok(())
+async fn download_async(
+    song: QueuedSong,
+    dir: PathBuf,
+    folder_template: FolderTemplate,
+    updater: DownloadUpdater,
+) -> (String, Result<Option<DownloadWarning>, APIErr>) {
+    let id = song.song_info.id.clone();
+    let progress_id = id.clone();
+    let cancel_updater = updater.clone();
+    let on_progress =
+        move |received, total| updater.set_song_progress(&progress_id, received, total);
+    let result = beatsaver::download_and_unzip_song(
+        song.song_info,
+        dir,
+        &folder_template,
+        song.replaces,
+        on_progress,
+        move || cancel_updater.is_cancelled(),
+    )
+    .await;
+    (id, result)
 }
 
 #[derive(Clone)]
@@ -147,6 +631,81 @@ impl DownloadObserver {
             .clone()
     }
 
+    /// Songs that were downloaded, but not quite as the list asked for.
+    pub fn warnings(&self) -> Vec<(String, DownloadWarning)> {
+        self.info
+            .warnings
+            .lock()
+            .expect(POISONED_MUTEX_MESSAGE)
+            .clone()
+    }
+
+    /// Every song that will be downloaded, available once their info has been looked up.
+    pub fn songs(&self) -> Vec<SongInfo> {
+        self.info
+            .songs
+            .lock()
+            .expect(POISONED_MUTEX_MESSAGE)
+            .clone()
+    }
+
+    /// How far along each song in [`DownloadObserver::songs`] is, in the same order.
+    pub fn song_progress(&self) -> Vec<(SongInfo, SongProgress)> {
+        let songs = self.songs();
+        let progress = self.info.progress.lock().expect(POISONED_MUTEX_MESSAGE);
+        songs
+            .into_iter()
+            .map(|song_info| {
+                let song_progress = progress
+                    .songs
+                    .get(&song_info.id)
+                    .cloned()
+                    .unwrap_or_default();
+                (song_info, song_progress)
+            })
+            .collect()
+    }
+
+    /// What happened to each song that didn't simply download.
+    pub fn report(&self) -> DownloadReport {
+        let songs = self.songs();
+        let name = |key: &str| {
+            songs
+                .iter()
+                .find(|song_info| song_info.id.eq_ignore_ascii_case(key))
+                .map(|song_info| song_info.name.clone())
+                .unwrap_or_default()
+        };
+        DownloadReport {
+            downloaded: self.get_downloaded(),
+            cancelled: self.cancelled(),
+            failures: self
+                .failed_downloads()
+                .into_iter()
+                .map(|(key, err)| FailedSong {
+                    name: name(&key),
+                    cause: err.to_string(),
+                    key,
+                })
+                .collect(),
+            warnings: self
+                .warnings()
+                .into_iter()
+                .map(|(key, warning)| WarnedSong {
+                    name: name(&key),
+                    warning: warning.to_string(),
+                    key,
+                })
+                .collect(),
+        }
+    }
+
+    /// How far along the whole download is.
+    pub fn progress(&self) -> DownloadProgress {
+        let progress = self.info.progress.lock().expect(POISONED_MUTEX_MESSAGE);
+        progress.summary()
+    }
+
     pub fn set_max_concurrent_downloads(&self, n: NonZeroUsize) {
         self.info
             .max_concurrent_downloads
@@ -156,6 +715,32 @@ impl DownloadObserver {
     pub fn downloading(&self) -> bool {
         self.info.downloading.load(Ordering::Acquire)
     }
+
+    /// Stops new songs from starting, songs already downloading still finish.
+    pub fn pause(&self) {
+        self.info.paused.store(true, Ordering::Release);
+        self.info.state_changed.notify_waiters();
+    }
+
+    pub fn resume(&self) {
+        self.info.paused.store(false, Ordering::Release);
+        self.info.state_changed.notify_waiters();
+    }
+
+    pub fn paused(&self) -> bool {
+        self.info.paused.load(Ordering::Acquire)
+    }
+
+    /// Stops every download and removes what unfinished ones left behind. `downloading` turns
+    /// false once that's done.
+    pub fn cancel(&self) {
+        self.info.cancelled.store(true, Ordering::Release);
+        self.info.state_changed.notify_waiters();
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.info.cancelled.load(Ordering::Acquire)
+    }
 }
 
 #[derive(Clone)]
@@ -198,14 +783,117 @@ impl DownloadUpdater {
             .push((id, err));
     }
 
+    pub fn add_warning(&self, id: String, warning: DownloadWarning) {
+        self.info
+            .warnings
+            .lock()
+            .expect(POISONED_MUTEX_MESSAGE)
+            .push((id, warning));
+    }
+
+    /// Sets the songs that will be downloaded, with the size of their zip if it's known.
+    pub fn set_songs(&self, songs: Vec<(SongInfo, Option<u64>)>) {
+        let mut progress = self.info.progress.lock().expect(POISONED_MUTEX_MESSAGE);
+        progress.songs = songs
+            .iter()
+            .map(|(song_info, size)| {
+                let song_progress = SongProgress {
+                    total_bytes: *size,
+                    ..Default::default()
+                };
+                (song_info.id.clone(), song_progress)
+            })
+            .collect();
+        *self.info.songs.lock().expect(POISONED_MUTEX_MESSAGE) =
+            songs.into_iter().map(|(song_info, _)| song_info).collect();
+    }
+
+    /// Remembers the songs being downloaded so failed ones can be retried.
+    fn set_queued(&self, songs: Vec<QueuedSong>) {
+        *self.info.queued.lock().expect(POISONED_MUTEX_MESSAGE) = songs;
+    }
+
+    /// Remembers a song that couldn't be looked up so it can be retried.
+    fn add_unresolved(&self, song: SharedSong) {
+        self.info
+            .unresolved
+            .lock()
+            .expect(POISONED_MUTEX_MESSAGE)
+            .push(song);
+    }
+
+    /// Records that `received` bytes of a song's zip have been downloaded, out of `total`.
+    pub fn set_song_progress(&self, id: &str, received: u64, total: Option<u64>) {
+        let mut progress = self.info.progress.lock().expect(POISONED_MUTEX_MESSAGE);
+        let progress = &mut *progress;
+        let song = progress.songs.entry(id.to_string()).or_default();
+        // a retried request starts over
+        let previous = song.downloaded_bytes.min(received);
+        progress.downloaded_bytes += received - previous;
+        song.downloaded_bytes = received;
+        song.total_bytes = total.or(song.total_bytes);
+        song.state = SongState::Downloading;
+        progress.sample();
+    }
+
+    pub fn set_song_state(&self, id: &str, state: SongState) {
+        let mut progress = self.info.progress.lock().expect(POISONED_MUTEX_MESSAGE);
+        progress.songs.entry(id.to_string()).or_default().state = state;
+    }
+
     pub fn get_max_concurrent_downloads(&self) -> NonZeroUsize {
         let max_threads = self.info.max_concurrent_downloads.load(Ordering::Acquire);
         // the setters for max_concurrent_downloads only allow setting it to a NonZeroUsize, so unwrap is ok here
         NonZeroUsize::new(max_threads).unwrap()
     }
 
+    pub fn is_cancelled(&self) -> bool {
+        self.info.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Resolves once the download is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // created before checking so a cancel in between isn't missed
+            let notified = self.info.state_changed.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Waits for the download to be resumed if it's paused. Returns false if it's cancelled
+    /// instead.
+    pub async fn wait_while_paused(&self) -> bool {
+        loop {
+            let notified = self.info.state_changed.notified();
+            if self.is_cancelled() {
+                return false;
+            }
+            if !self.info.paused.load(Ordering::Acquire) {
+                return true;
+            }
+            notified.await;
+        }
+    }
+
+    /// Marks every song that didn't finish or fail as cancelled.
+    pub fn cancel_unfinished(&self) {
+        let mut progress = self.info.progress.lock().expect(POISONED_MUTEX_MESSAGE);
+        for song in progress.songs.values_mut() {
+            if matches!(song.state, SongState::Queued | SongState::Downloading) {
+                song.state = SongState::Cancelled;
+            }
+        }
+        self.info
+            .ongoing_downloads
+            .lock()
+            .expect(POISONED_MUTEX_MESSAGE)
+            .clear();
+    }
+
     pub fn set_downloading(&self, b: bool) {
-        println!("{}", b);
         self.info.downloading.store(b, Ordering::Release);
     }
 }
@@ -214,8 +902,17 @@ struct SharedInfo {
     downloaded: AtomicUsize,
     ongoing_downloads: Mutex<Vec<String>>,
     failed_downloads: Mutex<Vec<(String, APIErr)>>,
+    warnings: Mutex<Vec<(String, DownloadWarning)>>,
+    songs: Mutex<Vec<SongInfo>>,
+    progress: Mutex<ProgressInfo>,
+    queued: Mutex<Vec<QueuedSong>>,
+    unresolved: Mutex<Vec<SharedSong>>,
     max_concurrent_downloads: AtomicUsize,
     downloading: AtomicBool,
+    paused: AtomicBool,
+    cancelled: AtomicBool,
+    /// Notified when the download is paused, resumed or cancelled.
+    state_changed: tokio::sync::Notify,
 }
 
 impl SharedInfo {
@@ -224,8 +921,16 @@ impl SharedInfo {
             downloaded: Default::default(),
             ongoing_downloads: Default::default(),
             failed_downloads: Default::default(),
+            warnings: Default::default(),
+            songs: Default::default(),
+            progress: Default::default(),
+            queued: Default::default(),
+            unresolved: Default::default(),
             max_concurrent_downloads: AtomicUsize::new(max_concurrent_downloads.get()),
             downloading: Default::default(),
+            paused: Default::default(),
+            cancelled: Default::default(),
+            state_changed: Default::default(),
         }
     }
 
@@ -240,38 +945,498 @@ impl SharedInfo {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct SongInfo {
-    id: String,
-    name: String,
-    author: String,
-    download_url: String,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SongState {
+    #[default]
+    Queued,
+    Downloading,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SongProgress {
+    pub state: SongState,
+    pub downloaded_bytes: u64,
+    /// Size of the song's zip, once BeatSaver has said.
+    pub total_bytes: Option<u64>,
+}
+
+impl SongProgress {
+    /// From 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        match (self.state, self.total_bytes) {
+            (SongState::Done | SongState::Failed | SongState::Cancelled, _) => 1.0,
+            (_, Some(total)) if total > 0 => (self.downloaded_bytes as f64 / total as f64) as f32,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DownloadProgress {
+    /// Bytes downloaded across every song so far.
+    pub downloaded_bytes: u64,
+    /// Bytes to download across every song. Songs whose size isn't known yet are guessed to be
+    /// the average of those that are, `None` if none are known.
+    pub total_bytes: Option<u64>,
+    /// Download speed over the last few seconds.
+    pub bytes_per_second: f64,
+    /// Estimated time left, `None` until there's enough to go on.
+    pub eta: Option<Duration>,
+    /// From 0 to 1.
+    pub fraction: f32,
+}
+
+/// How far back download speed is measured.
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct ProgressInfo {
+    songs: HashMap<String, SongProgress>,
+    downloaded_bytes: u64,
+    /// When `downloaded_bytes` reached each amount, over the last `SPEED_WINDOW`.
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl ProgressInfo {
+    fn sample(&mut self) {
+        let now = Instant::now();
+        self.samples.push_back((now, self.downloaded_bytes));
+        // keep one sample from before the window to measure from
+        while self.samples.len() > 2 && now - self.samples[1].0 > SPEED_WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    fn bytes_per_second(&self) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some((start, start_bytes)), Some((_, end_bytes))) => {
+                // stalled downloads add no samples, so measure up to now
+                let elapsed = start.elapsed().as_secs_f64();
+                if elapsed > 0.0 {
+                    (end_bytes - start_bytes) as f64 / elapsed
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn summary(&self) -> DownloadProgress {
+        let known: Vec<u64> = self
+            .songs
+            .values()
+            .filter_map(|song| song.total_bytes)
+            .collect();
+        let known_total: u64 = known.iter().sum();
+        let total_bytes = match known.len() {
+            0 => None,
+            n => {
+                let average = known_total / n as u64;
+                Some(known_total + average * (self.songs.len() - n) as u64)
+            }
+        };
+
+        let bytes_per_second = self.bytes_per_second();
+        let eta = total_bytes.filter(|_| bytes_per_second > 0.0).map(|total| {
+            let left = total.saturating_sub(self.downloaded_bytes);
+            Duration::from_secs_f64(left as f64 / bytes_per_second)
+        });
+        let fraction = match self.songs.len() {
+            0 => 0.0,
+            n => self.songs.values().map(SongProgress::fraction).sum::<f32>() / n as f32,
+        };
+
+        DownloadProgress {
+            downloaded_bytes: self.downloaded_bytes,
+            total_bytes,
+            bytes_per_second,
+            eta,
+            fraction,
+        }
+    }
+}
+
+/// A song in a shared list, pinned to the exact version the uploader has when it's known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedSong {
+    pub key: String,
+    /// Version hash, lowercase hex. Lists uploaded by older versions of beat-sharer don't have it.
+    pub hash: Option<String>,
+}
+
+impl SharedSong {
+    /// Parses the `key` or `key:hash` form lists are stored in.
+    pub fn parse(s: &str) -> Self {
+        match s.split_once(':') {
+            Some((key, hash)) => Self {
+                key: key.to_string(),
+                hash: Some(hash.to_lowercase()),
+            },
+            None => Self {
+                key: s.to_string(),
+                hash: None,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for SharedSong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.hash {
+            Some(hash) => write!(f, "{}:{}", self.key, hash),
+            None => write!(f, "{}", self.key),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
+pub enum DownloadWarning {
+    /// BeatSaver no longer has the version the list was pinned to, the latest one was downloaded.
+    PinnedVersionUnavailable,
+    /// The map's format isn't hashed the way we check downloads, so it was installed unchecked.
+    Unverified,
+}
+
+impl std::fmt::Display for DownloadWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadWarning::PinnedVersionUnavailable => write!(
+                f,
+                "BeatSaver no longer has the shared version, the latest version was downloaded"
+            ),
+            DownloadWarning::Unverified => write!(
+                f,
+                "installed without checking its hash, the map's format isn't hashed that way"
+            ),
+        }
+    }
+}
+
+/// A song on BeatSaver, at one of its versions.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SongInfo {
+    /// BeatSaver key, e.g. `1a2b`.
+    pub id: String,
+    pub name: String,
+    /// The mapper, as written in the map itself.
+    pub author: String,
+    pub download_url: String,
+    /// SHA1 of the version, lowercase hex.
+    pub hash: String,
+    pub bpm: f32,
+    /// Length in seconds.
+    pub duration: u32,
+    pub difficulties: Vec<Difficulty>,
+    /// The BeatSaver account that uploaded the map.
+    pub uploader: String,
+    /// BeatSaver's rating, from 0 to 1.
+    pub rating: f32,
+    /// ISO 8601 timestamp, empty if BeatSaver didn't send one.
+    pub upload_date: String,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Difficulty {
+    /// e.g. `Standard`, `OneSaber`, `Lawless`.
+    pub characteristic: String,
+    /// e.g. `Easy`, `ExpertPlus`.
+    pub difficulty: String,
+    /// Notes per second.
+    pub nps: f32,
+    pub notes: u32,
+}
+
+#[derive(Debug, Clone)]
 pub enum APIErr {
-    IndexNotFound,
-    ReqwestFailed,
-    SongNotFound,
-    FileCreationFailed,
-    InvalidText,
-    UnzipFailed,
+    /// There's no list stored under `index`.
+    IndexNotFound {
+        index: u32,
+    },
+    /// A request couldn't be sent, or its response couldn't be read.
+    Request {
+        url: Option<String>,
+        source: Arc<reqwest::Error>,
+    },
+    /// Nothing came back within the configured timeout.
+    Timeout {
+        url: Option<String>,
+    },
+    /// The server answered with an error status.
+    Status {
+        url: Option<String>,
+        status: reqwest::StatusCode,
+    },
+    /// BeatSaver doesn't have a song with this key.
+    SongNotFound {
+        key: String,
+    },
+    /// BeatSaver doesn't have a version with this hash.
+    VersionNotFound {
+        hash: String,
+    },
+    Io {
+        path: Option<PathBuf>,
+        source: Arc<io::Error>,
+    },
+    /// A response wasn't what we expected.
+    InvalidResponse {
+        url: Option<String>,
+        reason: String,
+    },
+    Unzip {
+        path: Option<PathBuf>,
+        source: Arc<ZipError>,
+    },
+    ListAlreadyExists {
+        index: u32,
+    },
+    IndexContention,
+    IndexExhausted,
+    /// A song's zip isn't safe to extract, `reason` says why.
+    UnsafeArchive {
+        reason: String,
+    },
+    /// A downloaded song's zip has no `Info.dat`.
+    NotAMap {
+        key: String,
+    },
+    /// A downloaded song's files don't hash to the version BeatSaver listed.
+    HashMismatch {
+        key: String,
+        expected: String,
+        actual: String,
+    },
+    /// The download was cancelled before the song was moved into the songs folder.
+    Cancelled,
+}
+
+impl APIErr {
+    pub(crate) fn invalid_response(url: &str, reason: impl std::fmt::Display) -> Self {
+        APIErr::InvalidResponse {
+            url: Some(redact_url(url)),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// Drops the query from a URL, it can hold the database's auth token.
+pub(crate) fn redact_url(url: &str) -> String {
+    match url.split_once('?') {
+        Some((url, _)) => url.to_string(),
+        None => url.to_string(),
+    }
+}
+
+/// The error's message followed by those of whatever caused it, reqwest in particular keeps the
+/// useful part (DNS, TLS, ...) a few errors down.
+fn source_chain(err: &dyn std::error::Error) -> String {
+    let mut chain = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        // some errors already print their source
+        let message = err.to_string();
+        if !chain.contains(&message) {
+            chain.push_str(": ");
+            chain.push_str(&message);
+        }
+        source = err.source();
+    }
+    chain
+}
+
+/// Short enough to show in the window, with the cause written out since that's usually all a
+/// user gets to see.
+impl std::fmt::Display for APIErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            APIErr::IndexNotFound { index } => write!(f, "there's no list with ID {}", index),
+            APIErr::Request {
+                url: Some(url),
+                source,
+            } => write!(f, "couldn't reach {}: {}", url, source_chain(source)),
+            APIErr::Request { url: None, source } => {
+                write!(f, "couldn't reach the server: {}", source_chain(source))
+            }
+            APIErr::Timeout { url: Some(url) } => write!(f, "{} took too long to answer", url),
+            APIErr::Timeout { url: None } => write!(f, "the server took too long to answer"),
+            APIErr::Status {
+                url: Some(url),
+                status,
+            } => write!(f, "{} answered {}", url, status),
+            APIErr::Status { url: None, status } => write!(f, "the server answered {}", status),
+            APIErr::SongNotFound { key } => write!(f, "BeatSaver doesn't have song {}", key),
+            APIErr::VersionNotFound { hash } => {
+                write!(f, "BeatSaver doesn't have the version {}", hash)
+            }
+            APIErr::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "couldn't access {}: {}", path.display(), source),
+            APIErr::Io { path: None, source } => write!(f, "couldn't access a file: {}", source),
+            APIErr::InvalidResponse {
+                url: Some(url),
+                reason,
+            } => write!(f, "unexpected response from {}: {}", url, reason),
+            APIErr::InvalidResponse { url: None, reason } => {
+                write!(f, "unexpected response: {}", reason)
+            }
+            APIErr::Unzip {
+                path: Some(path),
+                source,
+            } => write!(f, "couldn't extract {}: {}", path.display(), source),
+            APIErr::Unzip { path: None, source } => {
+                write!(f, "couldn't extract the song's zip: {}", source)
+            }
+            APIErr::ListAlreadyExists { index } => {
+                write!(f, "a list with ID {} already exists", index)
+            }
+            APIErr::IndexContention => {
+                write!(f, "too many lists were uploaded at once, try again")
+            }
+            APIErr::IndexExhausted => write!(f, "there are no list IDs left"),
+            APIErr::UnsafeArchive { reason } => {
+                write!(f, "the song's zip isn't safe to extract: {}", reason)
+            }
+            APIErr::Cancelled => write!(f, "the download was cancelled"),
+            APIErr::NotAMap { key } => write!(f, "song {}'s zip has no Info.dat", key),
+            APIErr::HashMismatch {
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "song {} downloaded as version {} instead of {}",
+                key, actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for APIErr {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            APIErr::Request { source, .. } => Some(source.as_ref()),
+            APIErr::Io { source, .. } => Some(source.as_ref()),
+            APIErr::Unzip { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for APIErr {
+    fn from(err: reqwest::Error) -> Self {
+        let url = err.url().map(|url| redact_url(url.as_str()));
+        // the URL is kept separately, without the auth token reqwest would print
+        let err = err.without_url();
+        if let Some(status) = err.status() {
+            APIErr::Status { url, status }
+        } else if err.is_timeout() {
+            APIErr::Timeout { url }
+        } else {
+            APIErr::Request {
+                url,
+                source: Arc::new(err),
+            }
+        }
+    }
 }
 
 macro_rules! impl_from_error_to_api_err {
-    ($($from: ty, $err: expr),+) => {
+    ($($from: ty, $err: ident => $api_err: expr),+) => {
         $(
             impl From<$from> for APIErr {
-                fn from(_: $from) -> Self {
-                    $err
+                fn from($err: $from) -> Self {
+                    $api_err
                 }
             }
         )+
     };
 }
 
+// errors without a path, see `WithPath` to add one
 impl_from_error_to_api_err! {
-    reqwest::Error, APIErr::ReqwestFailed,
-    io::Error, APIErr::FileCreationFailed,
-    ZipError, APIErr::UnzipFailed
+    io::Error, err => APIErr::Io { path: None, source: Arc::new(err) },
+    ZipError, err => APIErr::Unzip { path: None, source: Arc::new(err) },
+    serde_json::Error, err => APIErr::InvalidResponse { url: None, reason: err.to_string() }
+}
+
+/// Attaches the path a file operation failed on to its error.
+pub(crate) trait WithPath<T> {
+    fn with_path(self, path: &Path) -> Result<T, APIErr>;
+}
+
+impl<T> WithPath<T> for io::Result<T> {
+    fn with_path(self, path: &Path) -> Result<T, APIErr> {
+        self.map_err(|err| APIErr::Io {
+            path: Some(path.to_path_buf()),
+            source: Arc::new(err),
+        })
+    }
+}
+
+impl<T> WithPath<T> for zip::result::ZipResult<T> {
+    fn with_path(self, path: &Path) -> Result<T, APIErr> {
+        self.map_err(|err| APIErr::Unzip {
+            path: Some(path.to_path_buf()),
+            source: Arc::new(err),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song_info(hash: &str) -> SongInfo {
+        SongInfo {
+            id: String::from("1a2b"),
+            name: String::from("Song"),
+            author: String::from("Mapper"),
+            download_url: String::new(),
+            hash: String::from(hash),
+            bpm: 120.0,
+            duration: 180,
+            difficulties: Vec::new(),
+            uploader: String::from("Uploader"),
+            rating: 0.9,
+            upload_date: String::new(),
+        }
+    }
+
+    #[test]
+    fn picks_the_pinned_version() {
+        let song = SharedSong::parse("1a2b:AAAA");
+        let pinned = Ok(HashMap::from([(String::from("aaaa"), song_info("aaaa"))]));
+        let (picked, warning) = pick_version(&song, song_info("bbbb"), &pinned).unwrap();
+        assert_eq!(picked.hash, "aaaa");
+        assert_eq!(warning, None);
+
+        // already the latest, so it was never looked up
+        let (picked, warning) =
+            pick_version(&song, song_info("AAAA"), &Ok(HashMap::new())).unwrap();
+        assert_eq!(picked.hash, "AAAA");
+        assert_eq!(warning, None);
+    }
+
+    #[test]
+    fn falls_back_only_when_the_pinned_version_is_gone() {
+        let song = SharedSong::parse("1a2b:aaaa");
+        let (picked, warning) =
+            pick_version(&song, song_info("bbbb"), &Ok(HashMap::new())).unwrap();
+        assert_eq!(picked.hash, "bbbb");
+        assert_eq!(warning, Some(DownloadWarning::PinnedVersionUnavailable));
+
+        let failed = Err(APIErr::Timeout { url: None });
+        assert!(matches!(
+            pick_version(&song, song_info("bbbb"), &failed),
+            Err(APIErr::Timeout { .. })
+        ));
+        // songs that aren't pinned don't care
+        let unpinned = SharedSong::parse("1a2b");
+        assert!(pick_version(&unpinned, song_info("bbbb"), &failed).is_ok());
+    }
 }
@@ -0,0 +1,137 @@
+use crate::api::*;
+use crate::util::sanitize_folder_name;
+
+/// How a downloaded song's folder is named.
+///
+/// `{key}`, `{hash}`, `{name}`, `{author}` and `{uploader}` are replaced with the song's details,
+/// then the whole name is made safe to use as a folder name. Templates without `{key}` or
+/// `{hash}` get the key put in front, otherwise songs would end up sharing a folder.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct FolderTemplate(pub String);
+
+impl FolderTemplate {
+    /// The naming BeatSaver and most mod managers use, `1a2b (Song - Mapper)`.
+    pub const BEATSAVER: &'static str = "{key} ({name} - {author})";
+    pub const KEY_ONLY: &'static str = "{key}";
+    pub const HASH: &'static str = "{hash}";
+
+    /// Whether the template gives every song its own folder by itself.
+    pub fn is_unique(&self) -> bool {
+        self.0.contains("{key}") || self.0.contains("{hash}")
+    }
+
+    pub fn folder_name(&self, song_info: &SongInfo) -> String {
+        // substituted in one pass so a song called "{author}" stays called that
+        let mut name = String::new();
+        if !self.is_unique() {
+            // in front, so it isn't cut off when a long name is shortened
+            name.push_str(&song_info.id);
+            name.push(' ');
+        }
+        let mut rest = self.0.as_str();
+        while let Some(start) = rest.find('{') {
+            name.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let placeholder = rest.find('}').map(|end| &rest[..=end]);
+            let value = match placeholder {
+                Some("{key}") => &song_info.id,
+                Some("{hash}") => &song_info.hash,
+                Some("{name}") => &song_info.name,
+                Some("{author}") => &song_info.author,
+                Some("{uploader}") => &song_info.uploader,
+                _ => {
+                    name.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            name.push_str(value);
+            rest = &rest[placeholder.map_or(0, str::len)..];
+        }
+        name.push_str(rest);
+        sanitize_folder_name(&name)
+    }
+}
+
+impl Default for FolderTemplate {
+    fn default() -> Self {
+        FolderTemplate(String::from(Self::BEATSAVER))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song_info() -> SongInfo {
+        SongInfo {
+            id: String::from("1a2b"),
+            name: String::from("Song"),
+            author: String::from("Mapper"),
+            download_url: String::new(),
+            hash: String::from("abc123"),
+            bpm: 120.0,
+            duration: 180,
+            difficulties: Vec::new(),
+            uploader: String::from("Uploader"),
+            rating: 0.9,
+            upload_date: String::new(),
+        }
+    }
+
+    fn folder_name(template: &str, song_info: &SongInfo) -> String {
+        FolderTemplate(String::from(template)).folder_name(song_info)
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        let song_info = song_info();
+        assert_eq!(
+            folder_name(FolderTemplate::BEATSAVER, &song_info),
+            "1a2b (Song - Mapper)"
+        );
+        assert_eq!(folder_name(FolderTemplate::KEY_ONLY, &song_info), "1a2b");
+        assert_eq!(folder_name(FolderTemplate::HASH, &song_info), "abc123");
+        assert_eq!(folder_name("{uploader}-{key}", &song_info), "Uploader-1a2b");
+    }
+
+    #[test]
+    fn leaves_unknown_placeholders_and_braces() {
+        let song_info = song_info();
+        assert_eq!(
+            folder_name("{key} {nope} {key", &song_info),
+            "1a2b {nope} {key"
+        );
+        assert_eq!(folder_name("{{key}}", &song_info), "{1a2b}");
+    }
+
+    #[test]
+    fn doesnt_substitute_inside_values() {
+        let mut song_info = song_info();
+        song_info.name = String::from("{author}");
+        assert_eq!(folder_name("{key} {name}", &song_info), "1a2b {author}");
+    }
+
+    #[test]
+    fn adds_the_key_to_templates_without_one() {
+        let song_info = song_info();
+        assert_eq!(folder_name("{name}", &song_info), "1a2b Song");
+        assert_eq!(folder_name("", &song_info), "1a2b");
+        assert_eq!(folder_name("{hash}", &song_info), "abc123");
+
+        let mut long_name = song_info.clone();
+        long_name.name = "x".repeat(300);
+        assert!(folder_name("{name}", &long_name).starts_with("1a2b "));
+    }
+
+    #[test]
+    fn sanitizes_values() {
+        let mut song_info = song_info();
+        song_info.name = String::from("../../evil");
+        song_info.author = String::from("a/b");
+        assert_eq!(
+            folder_name(FolderTemplate::BEATSAVER, &song_info),
+            "1a2b (.._.._evil - a_b)"
+        );
+    }
+}
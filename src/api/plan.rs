@@ -0,0 +1,157 @@
+use super::{
+    beatsaver, get_pinned_versions, pick_version, save_cache, unique_songs, APIErr,
+    DownloadWarning, SharedSong, SongInfo,
+};
+use crate::library::Library;
+use futures::StreamExt;
+use std::path::PathBuf;
+
+/// How many download sizes are asked for at once.
+const MAX_CONCURRENT_SIZE_REQUESTS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlanStatus {
+    /// Not installed.
+    New,
+    /// The list's version is already installed.
+    Present,
+    /// Installed, but not the list's version.
+    Outdated,
+    /// BeatSaver doesn't have it, or couldn't be asked for its shared version, see
+    /// [`PlannedSong::error`].
+    Unavailable,
+}
+
+/// A song in a shared list and what downloading it would do.
+#[derive(Clone, Debug)]
+pub struct PlannedSong {
+    pub key: String,
+    pub status: PlanStatus,
+    /// The version that would be downloaded, `None` if the song is unavailable.
+    pub song_info: Option<SongInfo>,
+    /// Song name and mapper, from BeatSaver or the installed copy.
+    pub name: String,
+    pub level_author: String,
+    /// The installed version an outdated song's download replaces.
+    pub replaces: Option<PathBuf>,
+    /// Download size in bytes, for songs that would be downloaded, if BeatSaver says.
+    pub size: Option<u64>,
+    pub warning: Option<DownloadWarning>,
+    /// Why an unavailable song couldn't be looked up, `None` if BeatSaver just doesn't have it.
+    pub error: Option<APIErr>,
+    /// Whether the song will be downloaded. New and outdated songs start selected.
+    pub selected: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DownloadPlan {
+    pub songs: Vec<PlannedSong>,
+}
+
+impl DownloadPlan {
+    pub fn count(&self, status: PlanStatus) -> usize {
+        self.songs
+            .iter()
+            .filter(|song| song.status == status)
+            .count()
+    }
+
+    pub fn selected_count(&self) -> usize {
+        self.songs.iter().filter(|song| song.selected).count()
+    }
+
+    /// Total size of the selected songs whose size is known, in bytes.
+    pub fn selected_size(&self) -> u64 {
+        self.songs
+            .iter()
+            .filter(|song| song.selected)
+            .filter_map(|song| song.size)
+            .sum()
+    }
+}
+
+pub(super) async fn plan_async(
+    list: Vec<SharedSong>,
+    library: Library,
+) -> Result<DownloadPlan, APIErr> {
+    let list = unique_songs(&list);
+    let ids: Vec<String> = list.iter().map(|song| song.key.clone()).collect();
+    let latest = beatsaver::get_song_infos(&ids).await?;
+    let pinned = get_pinned_versions(&list, &latest).await;
+    save_cache().await;
+
+    let hashes = library.hashes();
+    let mut songs = Vec::with_capacity(list.len());
+    for song in &list {
+        let installed = library.songs.iter().find(|local| {
+            local
+                .key
+                .as_ref()
+                .is_some_and(|key| key.eq_ignore_ascii_case(&song.key))
+        });
+        let latest_info = latest.get(&song.key.to_lowercase());
+        let (song_info, warning, error) = match latest_info {
+            Some(latest_info) => match pick_version(song, latest_info.clone(), &pinned) {
+                Ok((song_info, warning)) => (Some(song_info), warning, None),
+                // the rest of the list can still be downloaded
+                Err(err) => (None, None, Some(err)),
+            },
+            None => (None, None, None),
+        };
+
+        let status = match (&song_info, installed) {
+            (None, _) => PlanStatus::Unavailable,
+            // installed under a folder name we couldn't get the key from
+            (Some(song_info), _) if hashes.contains(&song_info.hash) => PlanStatus::Present,
+            (Some(_), None) => PlanStatus::New,
+            // maps in a format we can't hash can't be compared, so leave them be
+            (Some(_), Some(installed)) if installed.hash.is_none() => PlanStatus::Present,
+            (Some(_), Some(_)) => PlanStatus::Outdated,
+        };
+        let (name, level_author) = match (song_info.as_ref().or(latest_info), installed) {
+            (Some(song_info), _) => (song_info.name.clone(), song_info.author.clone()),
+            (None, Some(installed)) => (installed.name.clone(), installed.level_author.clone()),
+            (None, None) => Default::default(),
+        };
+        songs.push(PlannedSong {
+            key: song.key.clone(),
+            status,
+            song_info,
+            name,
+            level_author,
+            replaces: installed
+                .filter(|_| status == PlanStatus::Outdated)
+                .map(|installed| installed.path.clone()),
+            size: None,
+            warning,
+            error,
+            selected: matches!(status, PlanStatus::New | PlanStatus::Outdated),
+        });
+    }
+
+    let to_download: Vec<(usize, SongInfo)> = songs
+        .iter()
+        .enumerate()
+        .filter(|(_, song)| song.selected)
+        .filter_map(|(i, song)| Some((i, song.song_info.clone()?)))
+        .collect();
+    let sizes: Vec<(usize, Option<u64>)> = futures::stream::iter(to_download)
+        .map(|(i, song_info)| async move {
+            // only for show, a song without a size still downloads
+            (
+                i,
+                beatsaver::get_download_size(&song_info)
+                    .await
+                    .ok()
+                    .flatten(),
+            )
+        })
+        .buffer_unordered(MAX_CONCURRENT_SIZE_REQUESTS)
+        .collect()
+        .await;
+    for (i, size) in sizes {
+        songs[i].size = size;
+    }
+
+    Ok(DownloadPlan { songs })
+}
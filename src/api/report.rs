@@ -0,0 +1,95 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// What happened to a finished download, to show or share.
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct DownloadReport {
+    pub downloaded: usize,
+    /// Whether the download was cancelled before every song was tried.
+    pub cancelled: bool,
+    pub failures: Vec<FailedSong>,
+    pub warnings: Vec<WarnedSong>,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct FailedSong {
+    pub key: String,
+    /// Empty if the song couldn't be looked up.
+    pub name: String,
+    /// Why it failed, readable by people.
+    pub cause: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct WarnedSong {
+    pub key: String,
+    pub name: String,
+    pub warning: String,
+}
+
+impl DownloadReport {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Writes the report to `path`, as JSON if it ends in `.json` and as text otherwise.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        let contents = if is_json {
+            self.to_json()?
+        } else {
+            self.to_string()
+        };
+        std::fs::write(path, contents)
+    }
+}
+
+impl fmt::Display for DownloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Downloaded {} songs, {} failed",
+            self.downloaded,
+            self.failures.len()
+        )?;
+        if self.cancelled {
+            write!(f, ", cancelled before the rest were downloaded")?;
+        }
+        writeln!(f)?;
+
+        if !self.failures.is_empty() {
+            writeln!(f, "\nFailed:")?;
+            for failure in &self.failures {
+                writeln!(
+                    f,
+                    "{}: {}",
+                    song_label(&failure.key, &failure.name),
+                    failure.cause
+                )?;
+            }
+        }
+        if !self.warnings.is_empty() {
+            writeln!(f, "\nWarnings:")?;
+            for warning in &self.warnings {
+                writeln!(
+                    f,
+                    "{}: {}",
+                    song_label(&warning.key, &warning.name),
+                    warning.warning
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn song_label(key: &str, name: &str) -> String {
+    if name.is_empty() {
+        key.to_string()
+    } else {
+        format!("{} ({})", key, name)
+    }
+}
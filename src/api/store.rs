@@ -0,0 +1,224 @@
+use crate::api::*;
+use futures::future::BoxFuture;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// Somewhere shared lists can be kept.
+///
+/// Every method returns a boxed future so stores can be swapped at runtime behind a
+/// `dyn ListStore`.
+pub trait ListStore: Send + Sync {
+    /// Claims a new, empty ID. The caller owns the ID and is the only one that may `put` to it.
+    fn allocate(&self) -> BoxFuture<'_, Result<u32, APIErr>>;
+
+    fn get(&self, index: u32) -> BoxFuture<'_, Result<Vec<SharedSong>, APIErr>>;
+
+    /// Stores `list` at an ID returned by `allocate`. An existing list is never overwritten.
+    fn put(&self, index: u32, list: Vec<SharedSong>) -> BoxFuture<'_, Result<(), APIErr>>;
+
+    fn delete(&self, index: u32) -> BoxFuture<'_, Result<(), APIErr>>;
+}
+
+/// Which `ListStore` the api functions talk to. Persisted by the app.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum StoreConfig {
+    /// The public beat-sharer database.
+    #[default]
+    Public,
+    /// Any server speaking the Firebase realtime database REST protocol.
+    Firebase { addr: String, auth: String },
+    /// A local or network-mounted folder, one file per list.
+    Directory { path: PathBuf },
+}
+
+impl StoreConfig {
+    pub fn build(&self) -> Arc<dyn ListStore> {
+        match self {
+            StoreConfig::Public => Arc::new(db::FirebaseStore::public(public_auth())),
+            StoreConfig::Firebase { addr, auth } => {
+                Arc::new(db::FirebaseStore::new(addr.clone(), auth.clone()))
+            }
+            StoreConfig::Directory { path } => Arc::new(DirectoryStore::new(path.clone())),
+        }
+    }
+}
+
+/// Stores each list as `<id>.txt` inside a folder, with one song per line.
+///
+/// IDs are claimed by creating the file exclusively, so several clients sharing the folder
+/// never end up with the same ID. A claimed but not yet uploaded list is an empty file.
+pub struct DirectoryStore {
+    dir: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn list_path(&self, index: u32) -> PathBuf {
+        self.dir.join(format!("{}.txt", index))
+    }
+
+    async fn next_free_index(dir: &Path) -> Result<u32, APIErr> {
+        let mut entries = tokio::fs::read_dir(dir).await.with_path(dir)?;
+        let mut next = 0;
+        while let Some(entry) = entries.next_entry().await.with_path(dir)? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                continue;
+            }
+            if let Some(index) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u32>().ok())
+            {
+                next = next.max(index.checked_add(1).ok_or(APIErr::IndexExhausted)?);
+            }
+        }
+        Ok(next)
+    }
+}
+
+impl ListStore for DirectoryStore {
+    fn allocate(&self) -> BoxFuture<'_, Result<u32, APIErr>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.dir)
+                .await
+                .with_path(&self.dir)?;
+            let mut index = Self::next_free_index(&self.dir).await?;
+            loop {
+                match tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(self.list_path(index))
+                    .await
+                {
+                    Ok(_) => return Ok(index),
+                    // another client claimed it between reading the folder and creating the file
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                        index = index.checked_add(1).ok_or(APIErr::IndexExhausted)?;
+                    }
+                    Err(err) => return Err(err).with_path(&self.list_path(index)),
+                }
+            }
+        })
+    }
+
+    fn get(&self, index: u32) -> BoxFuture<'_, Result<Vec<SharedSong>, APIErr>> {
+        Box::pin(async move {
+            let path = self.list_path(index);
+            let contents = match tokio::fs::read_to_string(&path).await {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    return Err(APIErr::IndexNotFound { index })
+                }
+                Err(err) => return Err(err).with_path(&path),
+            };
+            if contents.is_empty() {
+                return Err(APIErr::IndexNotFound { index });
+            }
+            Ok(contents.lines().map(SharedSong::parse).collect())
+        })
+    }
+
+    fn put(&self, index: u32, list: Vec<SharedSong>) -> BoxFuture<'_, Result<(), APIErr>> {
+        Box::pin(async move {
+            let path = self.list_path(index);
+            let mut file = match tokio::fs::OpenOptions::new().append(true).open(&path).await {
+                Ok(file) => file,
+                // put is only valid on an allocated ID
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    return Err(APIErr::IndexNotFound { index })
+                }
+                Err(err) => return Err(err).with_path(&path),
+            };
+            if file.metadata().await.with_path(&path)?.len() != 0 {
+                return Err(APIErr::ListAlreadyExists { index });
+            }
+            let lines: Vec<String> = list.iter().map(SharedSong::to_string).collect();
+            file.write_all(lines.join("\n").as_bytes())
+                .await
+                .with_path(&path)?;
+            file.flush().await.with_path(&path)?;
+            Ok(())
+        })
+    }
+
+    fn delete(&self, index: u32) -> BoxFuture<'_, Result<(), APIErr>> {
+        Box::pin(async move {
+            let path = self.list_path(index);
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    Err(APIErr::IndexNotFound { index })
+                }
+                Err(err) => Err(err).with_path(&path),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn songs(songs: &[&str]) -> Vec<SharedSong> {
+        songs.iter().map(|song| SharedSong::parse(song)).collect()
+    }
+
+    #[test]
+    fn allocates_unused_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(dir.path().join("lists"));
+        block_on(async {
+            assert_eq!(store.allocate().await.unwrap(), 0);
+            assert_eq!(store.allocate().await.unwrap(), 1);
+            // claimed, but nothing uploaded yet
+            assert!(matches!(
+                store.get(0).await,
+                Err(APIErr::IndexNotFound { index: 0 })
+            ));
+        });
+        std::fs::write(dir.path().join("lists/7.txt"), "1a2b").unwrap();
+        block_on(async { assert_eq!(store.allocate().await.unwrap(), 8) });
+    }
+
+    #[test]
+    fn never_overwrites_a_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(dir.path().to_path_buf());
+        block_on(async {
+            let index = store.allocate().await.unwrap();
+            let list = songs(&["1a2b", "3c4d:abc123"]);
+            store.put(index, list.clone()).await.unwrap();
+
+            assert!(matches!(
+                store.put(index, songs(&["ffff"])).await,
+                Err(APIErr::ListAlreadyExists { .. })
+            ));
+            assert_eq!(store.get(index).await.unwrap(), list);
+        });
+    }
+
+    #[test]
+    fn only_puts_to_allocated_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(dir.path().to_path_buf());
+        block_on(async {
+            assert!(matches!(
+                store.put(3, songs(&["1a2b"])).await,
+                Err(APIErr::IndexNotFound { index: 3 })
+            ));
+        });
+        assert!(!dir.path().join("3.txt").exists());
+    }
+}
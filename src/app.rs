@@ -1,19 +1,56 @@
-use crate::api;
-use crate::util::StringUtils;
+use beat_sharer::api;
+use beat_sharer::library::{Library, LibraryWatcher, LocalSong};
+use beat_sharer::songcore::{self, SongFolder};
+use beat_sharer::steam::{self, BeatSaberInstall};
+use beat_sharer::util::{format_bytes, format_duration};
+use notify_debouncer_mini::notify;
 use std::path::{Path, PathBuf};
 
 enum UploadStatus {
     NotStarted,
-    GettingIndex(tokio::sync::oneshot::Receiver<Result<u8, api::APIErr>>),
+    GettingIndex(tokio::sync::oneshot::Receiver<Result<u32, api::APIErr>>),
     Uploading(tokio::sync::oneshot::Receiver<Result<(), api::APIErr>>),
     Completed,
+    /// `index` is the ID we got, if it was the upload itself that failed.
+    Failed {
+        err: api::APIErr,
+        index: Option<u32>,
+    },
+}
+
+type LibraryScan = tokio::sync::oneshot::Receiver<std::io::Result<Library>>;
+
+/// Changed song folders, and their songs once they've been read.
+type LibraryUpdate = (Vec<PathBuf>, tokio::sync::oneshot::Receiver<Vec<LocalSong>>);
+
+type KeyLookup =
+    tokio::sync::oneshot::Receiver<Result<std::collections::HashMap<String, String>, api::APIErr>>;
+
+/// How long closing the window waits for a cancelled download to clean up.
+const EXIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+enum ErrorAction {
+    None,
+    Retry,
+    Dismiss,
+}
+
+enum PlanAction {
+    None,
+    Download,
+    Cancel,
 }
 
 enum DownloadStatus {
     NotStarted,
-    GettingList(tokio::sync::oneshot::Receiver<Result<Vec<String>, api::APIErr>>),
+    GettingList(tokio::sync::oneshot::Receiver<Result<Vec<api::SharedSong>, api::APIErr>>),
+    Planning(tokio::sync::oneshot::Receiver<Result<api::DownloadPlan, api::APIErr>>),
+    Reviewing(api::DownloadPlan),
     Downloading(api::DownloadObserver),
-    Completed,
+    /// Finished or cancelled, with what happened to the songs.
+    Completed(api::DownloadObserver, api::DownloadReport),
+    /// Getting or checking the list failed.
+    Failed(api::APIErr),
 }
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
@@ -21,28 +58,77 @@ enum DownloadStatus {
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
 pub struct BeatSharerApp {
     custom_level_path: PathBuf,
+    /// One of `song_folders`, `None` for `custom_level_path`.
+    download_path: Option<PathBuf>,
+    store_config: api::StoreConfig,
+    folder_template: api::FolderTemplate,
 
     #[serde(skip)]
-    codes: Vec<String>,
+    installs: Vec<BeatSaberInstall>,
+    #[serde(skip)]
+    song_folders: Vec<SongFolder>,
+    #[serde(skip)]
+    library: Library,
+    #[serde(skip)]
+    library_scan: Option<LibraryScan>,
+    #[serde(skip)]
+    library_update: Option<LibraryUpdate>,
+    #[serde(skip)]
+    library_watcher: Option<LibraryWatcher>,
+    #[serde(skip)]
+    library_error: Option<std::io::Error>,
+    /// Changes to the songs folder aren't picked up while this is set.
+    #[serde(skip)]
+    watcher_error: Option<notify::Error>,
+    #[serde(skip)]
+    key_lookup: Option<KeyLookup>,
+    /// The library changed while `key_lookup` was running, so it needs another one when it's done.
+    #[serde(skip)]
+    keys_stale: bool,
+    #[serde(skip)]
+    key_lookup_error: Option<api::APIErr>,
     #[serde(skip)]
     upload_status: UploadStatus,
     #[serde(skip)]
-    upload_code: u8,
+    upload_code: u32,
     #[serde(skip)]
     download_index_buf: String,
     #[serde(skip)]
+    download_index_error: Option<std::num::ParseIntError>,
+    /// The ID of the list being downloaded.
+    #[serde(skip)]
+    download_index: u32,
+    #[serde(skip)]
     download_status: DownloadStatus,
+    #[serde(skip)]
+    report_error: Option<std::io::Error>,
 }
 
 impl Default for BeatSharerApp {
     fn default() -> Self {
         Self {
-            custom_level_path: std::env::current_dir().unwrap(),
-            codes: get_codes(std::env::current_dir().unwrap()),
+            custom_level_path: std::env::current_dir().unwrap_or_default(),
+            download_path: None,
+            store_config: api::StoreConfig::default(),
+            folder_template: api::FolderTemplate::default(),
+            installs: Vec::new(),
+            song_folders: Vec::new(),
+            library: Library::default(),
+            library_scan: None,
+            library_update: None,
+            library_watcher: None,
+            library_error: None,
+            watcher_error: None,
+            key_lookup: None,
+            keys_stale: false,
+            key_lookup_error: None,
             upload_status: UploadStatus::NotStarted,
             upload_code: 0,
             download_index_buf: String::from(""),
+            download_index_error: None,
+            download_index: 0,
             download_status: DownloadStatus::NotStarted,
+            report_error: None,
         }
     }
 }
@@ -53,15 +139,93 @@ impl BeatSharerApp {
 
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
-        if let Some(storage) = cc.storage {
-            let mut temp: BeatSharerApp =
-                eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+        let stored: Option<BeatSharerApp> = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY));
+        let installs = steam::find_installs();
+        let mut app = match stored {
+            Some(app) => app,
+            // first run, start in the game's songs folder if we can find it
+            None => {
+                let mut app = BeatSharerApp::default();
+                if let Some(install) = installs.first() {
+                    app.select_install(install);
+                }
+                app
+            }
+        };
+        app.installs = installs;
 
-            temp.codes = get_codes(temp.custom_level_path.clone());
-            return temp;
+        api::set_store(&app.store_config);
+        app.load_library(&cc.egui_ctx);
+        // the last run may have been closed in the middle of a download
+        for folder in &app.song_folders {
+            let _ = api::clean_staging(&folder.path);
         }
+        app
+    }
+
+    fn select_install(&mut self, install: &BeatSaberInstall) {
+        self.custom_level_path = install.custom_levels();
+        // SongCore makes it on the first launch with mods, we might be earlier than that
+        let _ = std::fs::create_dir_all(&self.custom_level_path);
+    }
+
+    /// Starts scanning the selected folder and the game's other song folders, and watching them
+    /// for changes.
+    fn load_library(&mut self, ctx: &egui::Context) {
+        self.song_folders = songcore::song_folders(&self.custom_level_path);
+        let paths: Vec<PathBuf> = self
+            .song_folders
+            .iter()
+            .map(|folder| folder.path.clone())
+            .collect();
+        // the scan reads whatever an update in progress would have
+        self.library_update = None;
+        self.library_scan = Some(api::scan_library(paths.clone()));
+        let ctx = ctx.clone();
+        match LibraryWatcher::new(&paths, move || ctx.request_repaint()) {
+            Ok(watcher) => {
+                self.library_watcher = Some(watcher);
+                self.watcher_error = None;
+            }
+            Err(err) => {
+                self.library_watcher = None;
+                self.watcher_error = Some(err);
+            }
+        }
+    }
 
-        Default::default()
+    /// Uploads the library's list to `index`, an ID we've claimed.
+    fn upload(&self, index: u32) -> UploadStatus {
+        // pinned to the version we have so others get exactly what we play
+        let songs = self.library.shared_songs();
+        UploadStatus::Uploading(api::put_list(index, songs))
+    }
+
+    /// The folder new songs are downloaded into.
+    fn download_dir(&self) -> PathBuf {
+        self.download_path
+            .clone()
+            .filter(|path| self.song_folders.iter().any(|folder| &folder.path == path))
+            .unwrap_or_else(|| self.custom_level_path.clone())
+    }
+
+    /// Looks up the keys of songs that weren't named by key, after the lookup already running if
+    /// there is one.
+    fn look_up_keys(&mut self) {
+        if self.key_lookup.is_some() {
+            self.keys_stale = true;
+            return;
+        }
+        self.keys_stale = false;
+        self.key_lookup_error = None;
+        let hashes = self.library.unresolved_hashes();
+        self.key_lookup = if hashes.is_empty() {
+            None
+        } else {
+            Some(api::resolve_keys(hashes))
+        };
     }
 }
 
@@ -71,69 +235,230 @@ impl eframe::App for BeatSharerApp {
         eframe::set_value(storage, eframe::APP_KEY, self);
     }
 
+    /// Stops any download so it doesn't leave half a song behind.
+    fn on_exit(&mut self, _gl: &eframe::glow::Context) {
+        if let DownloadStatus::Downloading(download_observer) = &self.download_status {
+            download_observer.cancel();
+            let deadline = std::time::Instant::now() + EXIT_TIMEOUT;
+            while download_observer.downloading() && std::time::Instant::now() < deadline {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Handle Getting Index
-        if let UploadStatus::GettingIndex(r) = &mut self.upload_status {
-            // todo errors
-            if let Ok(upload_code) = r.try_recv() {
-                self.upload_code = upload_code.unwrap();
+        if let Some(r) = &mut self.library_scan {
+            // keep checking, nothing else wakes us up when it's done
+            ctx.request_repaint();
+            if let Ok(library) = r.try_recv() {
+                match library {
+                    Ok(library) => {
+                        self.library = library;
+                        self.library_error = None;
+                    }
+                    Err(err) => {
+                        self.library = Library::default();
+                        self.library_error = Some(err);
+                    }
+                }
+                self.library_scan = None;
+                self.look_up_keys();
+            }
+        }
+
+        // songs added by the game's downloader or another tool while we're open, one batch at a
+        // time, the watcher holds on to the rest
+        if self.library_scan.is_none() && self.library_update.is_none() {
+            if let Some(watcher) = &self.library_watcher {
+                let changed = watcher.take_changes();
+                if !changed.is_empty() {
+                    let songs = api::read_song_folders(changed.clone());
+                    self.library_update = Some((changed, songs));
+                }
+            }
+        }
 
-                let mut upload_string = String::new();
-                for c in &self.codes {
-                    upload_string.push_str(format!("{},", c).as_str());
+        if let Some((folders, r)) = &mut self.library_update {
+            ctx.request_repaint();
+            if let Ok(songs) = r.try_recv() {
+                self.library.replace_folders(folders, songs);
+                self.library_update = None;
+                self.look_up_keys();
+            }
+        }
+
+        if let Some(r) = &mut self.key_lookup {
+            if let Ok(keys) = r.try_recv() {
+                // songs BeatSaver doesn't know just won't be shared or matched by key, but a
+                // lookup that failed altogether is worth knowing about
+                match keys {
+                    Ok(keys) => self.library.apply_keys(&keys),
+                    Err(err) => self.key_lookup_error = Some(err),
                 }
+                self.key_lookup = None;
+                if self.keys_stale {
+                    self.look_up_keys();
+                }
+            }
+        }
 
-                self.upload_status =
-                    UploadStatus::Uploading(api::put_list(self.upload_code, upload_string));
+        // Handle Getting Index
+        if let UploadStatus::GettingIndex(r) = &mut self.upload_status {
+            if let Ok(upload_code) = r.try_recv() {
+                self.upload_status = match upload_code {
+                    Ok(upload_code) => {
+                        self.upload_code = upload_code;
+                        self.upload(upload_code)
+                    }
+                    Err(err) => UploadStatus::Failed { err, index: None },
+                };
             }
         } else if let UploadStatus::Uploading(r) = &mut self.upload_status {
-            // todo errors
-            if r.try_recv().is_ok() {
-                self.upload_status = UploadStatus::Completed;
+            if let Ok(result) = r.try_recv() {
+                self.upload_status = match result {
+                    Ok(()) => UploadStatus::Completed,
+                    Err(err) => UploadStatus::Failed {
+                        err,
+                        index: Some(self.upload_code),
+                    },
+                };
             }
         }
 
         if let DownloadStatus::GettingList(r) = &mut self.download_status {
-            // todo errors
             if let Ok(list) = r.try_recv() {
-                self.download_status = DownloadStatus::Downloading(api::download(
-                    list.unwrap(),
-                    self.codes.clone(),
-                    self.custom_level_path.clone(),
-                    std::num::NonZeroUsize::new(
-                        std::thread::available_parallelism()
-                            .unwrap_or(std::num::NonZeroUsize::new(1).unwrap())
-                            .get()
-                            * 2,
-                    )
-                    .unwrap(),
-                ))
+                self.download_status = match list {
+                    Ok(list) => {
+                        DownloadStatus::Planning(api::plan_download(list, self.library.clone()))
+                    }
+                    Err(err) => DownloadStatus::Failed(err),
+                };
+            }
+        }
+
+        if let DownloadStatus::Planning(r) = &mut self.download_status {
+            if let Ok(plan) = r.try_recv() {
+                self.download_status = match plan {
+                    Ok(plan) => DownloadStatus::Reviewing(plan),
+                    Err(err) => DownloadStatus::Failed(err),
+                };
             }
         }
 
         if let DownloadStatus::Downloading(download_observer) = &mut self.download_status {
-            // todo progress bar
+            // keep the progress moving
+            ctx.request_repaint();
             if !download_observer.downloading() {
-                self.download_status = DownloadStatus::Completed
+                let report = download_observer.report();
+                self.download_status = DownloadStatus::Completed(download_observer.clone(), report)
             }
         }
 
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical(|ui| {
                 ui.heading("Selected Folder");
-                ui.label(format!(
-                    "{} ({} Songs found)",
-                    self.custom_level_path.to_str().unwrap(),
-                    self.codes.len(),
-                ));
+                if self.library_scan.is_some() {
+                    ui.label(format!(
+                        "{} (Reading songs...)",
+                        self.custom_level_path.display()
+                    ));
+                } else {
+                    ui.label(format!(
+                        "{} ({} Songs found)",
+                        self.custom_level_path.display(),
+                        self.library.len(),
+                    ));
+                }
                 if ui.add(egui::Button::new("Change Folder")).clicked() {
                     if let Some(result) =
                         tinyfiledialogs::select_folder_dialog("Select CustomLevels Folder", ".")
                     {
                         self.custom_level_path = Path::new(&result).to_path_buf();
-                        self.codes = get_codes(self.custom_level_path.clone());
+                        self.load_library(ctx);
+                    }
+                }
+                if let Some(err) = &self.library_error {
+                    match error_ui(ui, "Couldn't read the songs folder", err, true) {
+                        ErrorAction::None => {}
+                        ErrorAction::Retry => self.load_library(ctx),
+                        ErrorAction::Dismiss => self.library_error = None,
+                    }
+                }
+                if let Some(err) = &self.watcher_error {
+                    match error_ui(ui, "Couldn't watch the songs folder for changes", err, true) {
+                        ErrorAction::None => {}
+                        ErrorAction::Retry => self.load_library(ctx),
+                        ErrorAction::Dismiss => self.watcher_error = None,
+                    }
+                }
+                if let Some(err) = &self.key_lookup_error {
+                    match error_ui(ui, "Couldn't look up songs on BeatSaver", err, true) {
+                        ErrorAction::None => {}
+                        ErrorAction::Retry => self.look_up_keys(),
+                        ErrorAction::Dismiss => self.key_lookup_error = None,
+                    }
+                }
+
+                if self.installs.len() > 1 {
+                    let mut selected = None;
+                    egui::ComboBox::from_label("Beat Saber install")
+                        .selected_text("Switch to...")
+                        .show_ui(ui, |ui| {
+                            for install in &self.installs {
+                                let current = install.custom_levels() == self.custom_level_path;
+                                let label = install.path.display().to_string();
+                                if ui.selectable_label(current, label).clicked() {
+                                    selected = Some(install.clone());
+                                }
+                            }
+                        });
+                    if let Some(install) = selected {
+                        self.select_install(&install);
+                        self.load_library(ctx);
                     }
                 }
+
+                for folder in self.song_folders.iter().skip(1) {
+                    ui.label(format!(
+                        "Also reading {} ({})",
+                        folder.name,
+                        folder.path.display()
+                    ));
+                }
+                if self.song_folders.len() > 1 {
+                    let download_dir = self.download_dir();
+                    let selected = self
+                        .song_folders
+                        .iter()
+                        .find(|folder| folder.path == download_dir)
+                        .map(|folder| folder.name.clone())
+                        .unwrap_or_default();
+                    egui::ComboBox::from_label("Download into")
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            for folder in &self.song_folders {
+                                ui.selectable_value(
+                                    &mut self.download_path,
+                                    Some(folder.path.clone()),
+                                    &folder.name,
+                                );
+                            }
+                        });
+                }
+            });
+
+            ui.separator();
+
+            let previous_store_config = self.store_config.clone();
+            egui::CollapsingHeader::new("Storage").show(ui, |ui| {
+                store_config_ui(ui, &mut self.store_config);
+            });
+            if self.store_config != previous_store_config {
+                api::set_store(&self.store_config);
+            }
+
+            egui::CollapsingHeader::new("Song Folder Names").show(ui, |ui| {
+                folder_template_ui(ui, &mut self.folder_template);
             });
 
             ui.separator();
@@ -148,7 +473,7 @@ impl eframe::App for BeatSharerApp {
                     if let UploadStatus::Completed = self.upload_status {
                         ui.label(format!(
                             "Uploaded {} songs to ID: {}",
-                            self.codes.len(),
+                            self.library.shared_songs().len(),
                             self.upload_code
                         ));
                     // Getting Index
@@ -157,14 +482,19 @@ impl eframe::App for BeatSharerApp {
                     // Uploading
                     } else if let UploadStatus::Uploading(_) = self.upload_status {
                         ui.label("Uploading...");
-                    // No songs
-                    } else if self.codes.is_empty() {
+                    // Failed, shown below
+                    } else if let UploadStatus::Failed { .. } = self.upload_status {
+                        ui.label("Upload failed");
+                    } else if self.library_scan.is_some() {
+                        ui.label("Reading songs...");
+                    // No songs, or none BeatSaver knows
+                    } else if self.library.shared_songs().is_empty() {
                         ui.label("Found no songs to upload");
                     // Click to upload
                     } else if ui
                         .add(egui::Button::new(format!(
                             "Upload {} songs",
-                            self.codes.len()
+                            self.library.shared_songs().len()
                         )))
                         .clicked()
                     {
@@ -183,13 +513,20 @@ impl eframe::App for BeatSharerApp {
                     ui.horizontal(|ui| {
                         if let DownloadStatus::GettingList(_) = self.download_status {
                             ui.label("Getting list...");
+                        } else if let DownloadStatus::Planning(_) = self.download_status {
+                            ui.label("Checking songs...");
+                        } else if let DownloadStatus::Reviewing(_) = self.download_status {
+                            ui.label("Pick the songs to download below");
                         } else if let DownloadStatus::Downloading(_) = self.download_status {
                             ui.label("Downloading Songs...");
+                        } else if let DownloadStatus::Failed(_) = self.download_status {
+                            ui.label("Download failed");
                             // todo allow to download with no other songs
-                        } else if self.codes.is_empty() {
+                        } else if self.library_scan.is_some() {
+                            ui.label("Reading songs...");
+                        } else if self.library.is_empty() {
                             ui.label("Are you sure your CustomLevels folder is selected?");
                         } else {
-                            // todo bad id
                             // todo dont allow multiple downloads
                             ui.add(
                                 egui::TextEdit::singleline(&mut self.download_index_buf)
@@ -197,38 +534,437 @@ impl eframe::App for BeatSharerApp {
                                     .desired_width(75.0),
                             );
                             if ui.add(egui::Button::new("Download Songs")).clicked() {
-                                if let Ok(index) = self.download_index_buf.parse::<u8>() {
-                                    self.download_status =
-                                        DownloadStatus::GettingList(api::get_list(index))
+                                match self.download_index_buf.trim().parse::<u32>() {
+                                    Ok(index) => {
+                                        self.download_index_error = None;
+                                        self.download_index = index;
+                                        self.download_status =
+                                            DownloadStatus::GettingList(api::get_list(index))
+                                    }
+                                    Err(err) => self.download_index_error = Some(err),
                                 }
                             }
                         }
                     });
                 });
             });
+
+            if let Some(err) = &self.download_index_error {
+                ui.separator();
+                let title = "That isn't a list ID, IDs are numbers";
+                if let ErrorAction::Dismiss = error_ui(ui, title, err, false) {
+                    self.download_index_error = None;
+                }
+            }
+
+            if let UploadStatus::Failed { err, index } = &self.upload_status {
+                ui.separator();
+                match error_ui(ui, "Upload failed", err, true) {
+                    ErrorAction::None => {}
+                    // an ID we already claimed is still ours to upload to
+                    ErrorAction::Retry => {
+                        self.upload_status = match *index {
+                            Some(index) => self.upload(index),
+                            None => UploadStatus::GettingIndex(api::get_and_inc_index()),
+                        }
+                    }
+                    ErrorAction::Dismiss => self.upload_status = UploadStatus::NotStarted,
+                }
+            }
+
+            if let DownloadStatus::Failed(err) = &self.download_status {
+                ui.separator();
+                // a list that isn't there won't be on a second try
+                let can_retry = !matches!(err, api::APIErr::IndexNotFound { .. });
+                match error_ui(ui, "Download failed", err, can_retry) {
+                    ErrorAction::None => {}
+                    ErrorAction::Retry => {
+                        self.download_status =
+                            DownloadStatus::GettingList(api::get_list(self.download_index))
+                    }
+                    ErrorAction::Dismiss => self.download_status = DownloadStatus::NotStarted,
+                }
+            }
+
+            if let DownloadStatus::Downloading(download_observer) = &self.download_status {
+                ui.separator();
+                download_progress_ui(ui, download_observer);
+            }
+
+            let action = match &self.download_status {
+                DownloadStatus::Completed(_, report) => {
+                    ui.separator();
+                    download_report_ui(ui, report, &mut self.report_error)
+                }
+                _ => ErrorAction::None,
+            };
+            match action {
+                ErrorAction::None => {}
+                ErrorAction::Retry => {
+                    if let DownloadStatus::Completed(download_observer, _) = &self.download_status {
+                        self.download_status = DownloadStatus::Downloading(api::retry_failed(
+                            download_observer,
+                            self.download_dir(),
+                            self.folder_template.clone(),
+                            api::default_max_concurrent_downloads(),
+                        ));
+                    }
+                }
+                ErrorAction::Dismiss => self.download_status = DownloadStatus::NotStarted,
+            }
+
+            let action = match &mut self.download_status {
+                DownloadStatus::Reviewing(plan) => {
+                    ui.separator();
+                    download_plan_ui(ui, plan)
+                }
+                _ => PlanAction::None,
+            };
+            match action {
+                PlanAction::None => {}
+                PlanAction::Download => {
+                    let status =
+                        std::mem::replace(&mut self.download_status, DownloadStatus::NotStarted);
+                    if let DownloadStatus::Reviewing(plan) = status {
+                        self.download_status = DownloadStatus::Downloading(api::download_plan(
+                            plan,
+                            self.download_dir(),
+                            self.folder_template.clone(),
+                            api::default_max_concurrent_downloads(),
+                        ));
+                    }
+                }
+                PlanAction::Cancel => self.download_status = DownloadStatus::NotStarted,
+            }
         });
     }
 }
 
-fn get_codes(path: PathBuf) -> Vec<String> {
-    let dir = path.read_dir().unwrap();
-    let mut codes: Vec<String> = Vec::new();
+/// Shows what went wrong, with buttons to try again or put it away.
+fn error_ui(
+    ui: &mut egui::Ui,
+    title: &str,
+    err: &dyn std::fmt::Display,
+    can_retry: bool,
+) -> ErrorAction {
+    let mut action = ErrorAction::None;
+    ui.colored_label(egui::Color32::LIGHT_RED, format!("{}: {}", title, err));
+    ui.horizontal(|ui| {
+        if can_retry && ui.add(egui::Button::new("Retry")).clicked() {
+            action = ErrorAction::Retry;
+        }
+        if ui.add(egui::Button::new("Dismiss")).clicked() {
+            action = ErrorAction::Dismiss;
+        }
+    });
+    action
+}
+
+/// Sums up a finished download, listing every song that failed and why.
+fn download_report_ui(
+    ui: &mut egui::Ui,
+    report: &api::DownloadReport,
+    save_error: &mut Option<std::io::Error>,
+) -> ErrorAction {
+    let mut action = ErrorAction::None;
+    if report.cancelled {
+        ui.label(format!(
+            "Download cancelled, downloaded {} songs",
+            report.downloaded
+        ));
+    } else {
+        ui.label(format!("Downloaded {} songs", report.downloaded));
+    }
+    if !report.failures.is_empty() {
+        ui.colored_label(
+            egui::Color32::LIGHT_RED,
+            format!("{} songs failed", report.failures.len()),
+        );
+    }
+
+    ui.horizontal(|ui| {
+        if !report.failures.is_empty() && ui.add(egui::Button::new("Retry Failed")).clicked() {
+            action = ErrorAction::Retry;
+        }
+        if !(report.failures.is_empty() && report.warnings.is_empty())
+            && ui.add(egui::Button::new("Save Report")).clicked()
+        {
+            if let Some(path) = tinyfiledialogs::save_file_dialog_with_filter(
+                "Save Download Report",
+                "download-report.txt",
+                &["*.txt", "*.json"],
+                "Text or JSON",
+            ) {
+                *save_error = report.save(Path::new(&path)).err();
+            }
+        }
+        if ui.add(egui::Button::new("Dismiss")).clicked() {
+            action = ErrorAction::Dismiss;
+        }
+    });
+    if let Some(err) = save_error {
+        // saving again is the retry
+        if let ErrorAction::Dismiss = error_ui(ui, "Couldn't save the report", err, false) {
+            *save_error = None;
+        }
+    }
+
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("download_report")
+            .striped(true)
+            .show(ui, |ui| {
+                for failure in &report.failures {
+                    ui.label(&failure.key);
+                    ui.label(&failure.name);
+                    ui.colored_label(egui::Color32::LIGHT_RED, &failure.cause);
+                    ui.end_row();
+                }
+                for warning in &report.warnings {
+                    ui.label(&warning.key);
+                    ui.label(&warning.name);
+                    ui.colored_label(egui::Color32::YELLOW, &warning.warning);
+                    ui.end_row();
+                }
+            });
+    });
+    action
+}
+
+/// Lists what downloading a shared list would do, for the user to pick the songs to download.
+fn download_plan_ui(ui: &mut egui::Ui, plan: &mut api::DownloadPlan) -> PlanAction {
+    let mut action = PlanAction::None;
+    ui.label(format!(
+        "{} new, {} outdated, {} already downloaded, {} unavailable",
+        plan.count(api::PlanStatus::New),
+        plan.count(api::PlanStatus::Outdated),
+        plan.count(api::PlanStatus::Present),
+        plan.count(api::PlanStatus::Unavailable),
+    ));
+    ui.horizontal(|ui| {
+        let selected = plan.selected_count();
+        let label = format!(
+            "Download {} songs ({})",
+            selected,
+            format_bytes(plan.selected_size())
+        );
+        if ui
+            .add_enabled(selected > 0, egui::Button::new(label))
+            .clicked()
+        {
+            action = PlanAction::Download;
+        }
+        if ui.add(egui::Button::new("Cancel")).clicked() {
+            action = PlanAction::Cancel;
+        }
+    });
+
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("download_plan")
+            .striped(true)
+            .show(ui, |ui| {
+                for song in &mut plan.songs {
+                    ui.add_enabled(
+                        song.song_info.is_some(),
+                        egui::Checkbox::new(&mut song.selected, ""),
+                    );
+                    let (color, status) = match song.status {
+                        api::PlanStatus::New => (egui::Color32::LIGHT_GREEN, "New"),
+                        api::PlanStatus::Outdated => (egui::Color32::YELLOW, "Outdated"),
+                        api::PlanStatus::Present => (egui::Color32::GRAY, "Downloaded"),
+                        api::PlanStatus::Unavailable => (egui::Color32::LIGHT_RED, "Unavailable"),
+                    };
+                    ui.colored_label(color, status);
+                    ui.label(&song.key);
+                    ui.label(&song.name);
+                    ui.label(&song.level_author);
+                    ui.label(song.size.map(format_bytes).unwrap_or_default());
+                    if song.warning.is_some() {
+                        ui.colored_label(egui::Color32::YELLOW, "Shared version gone, gets latest");
+                    } else if let Some(err) = &song.error {
+                        ui.colored_label(egui::Color32::LIGHT_RED, err.to_string());
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+    action
+}
+
+/// Overall progress, then a row for every song being downloaded, failed or done.
+fn download_progress_ui(ui: &mut egui::Ui, observer: &api::DownloadObserver) {
+    let progress = observer.progress();
+    let songs = observer.song_progress();
+    let failed = observer.failed_downloads();
 
-    for entry in dir {
-        let full_path = entry.unwrap().path();
+    ui.horizontal(|ui| {
+        if observer.cancelled() {
+            ui.label("Cancelling...");
+            return;
+        }
+        if observer.paused() {
+            if ui.add(egui::Button::new("Resume")).clicked() {
+                observer.resume();
+            }
+            ui.label("Paused, songs already downloading will finish");
+        } else if ui.add(egui::Button::new("Pause")).clicked() {
+            observer.pause();
+        }
+        if ui.add(egui::Button::new("Cancel")).clicked() {
+            observer.cancel();
+        }
+    });
 
-        let filename = full_path.file_name().unwrap();
+    ui.add(egui::ProgressBar::new(progress.fraction).text(format!(
+        "{} of {} songs",
+        observer.get_downloaded(),
+        songs.len()
+    )));
+    let total = progress
+        .total_bytes
+        .map(|total| format!(" of {}", format_bytes(total)))
+        .unwrap_or_default();
+    let eta = progress
+        .eta
+        .map(|eta| format!(", {} left", format_duration(eta)))
+        .unwrap_or_default();
+    ui.label(format!(
+        "{}{} at {}/s{}",
+        format_bytes(progress.downloaded_bytes),
+        total,
+        format_bytes(progress.bytes_per_second as u64),
+        eta
+    ));
 
-        let end = match filename.to_str().unwrap().find(" (") {
-            Some(t) => t,
-            None => continue,
-        };
-        let code = filename.to_str().unwrap().to_string().substring(0, end);
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("download_progress")
+            .striped(true)
+            .show(ui, |ui| {
+                let ongoing = observer.ongoing_downloads();
+                for (song_info, song_progress) in songs
+                    .iter()
+                    .filter(|(song_info, _)| ongoing.contains(&song_info.id))
+                {
+                    ui.label(&song_info.id);
+                    ui.label(&song_info.name);
+                    let bytes = match song_progress.total_bytes {
+                        Some(total) => format!(
+                            "{} of {}",
+                            format_bytes(song_progress.downloaded_bytes),
+                            format_bytes(total)
+                        ),
+                        None => format_bytes(song_progress.downloaded_bytes),
+                    };
+                    ui.add(
+                        egui::ProgressBar::new(song_progress.fraction())
+                            .desired_width(200.0)
+                            .text(bytes),
+                    );
+                    ui.end_row();
+                }
+                // failures include songs that couldn't be looked up, so they aren't in `songs`
+                for (key, err) in &failed {
+                    ui.label(key);
+                    let name = songs
+                        .iter()
+                        .find(|(song_info, _)| &song_info.id == key)
+                        .map(|(song_info, _)| song_info.name.as_str())
+                        .unwrap_or_default();
+                    ui.label(name);
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("Failed: {}", err));
+                    ui.end_row();
+                }
+                for (song_info, _) in songs
+                    .iter()
+                    .filter(|(_, song_progress)| song_progress.state == api::SongState::Done)
+                {
+                    ui.label(&song_info.id);
+                    ui.label(&song_info.name);
+                    ui.colored_label(egui::Color32::LIGHT_GREEN, "Done");
+                    ui.end_row();
+                }
+            });
+    });
+}
 
-        if code.chars().count() <= 5 {
-            codes.push(code);
+fn folder_template_ui(ui: &mut egui::Ui, folder_template: &mut api::FolderTemplate) {
+    for (template, label) in [
+        (api::FolderTemplate::BEATSAVER, "Key (Song - Mapper)"),
+        (api::FolderTemplate::KEY_ONLY, "Key"),
+        (api::FolderTemplate::HASH, "Hash"),
+    ] {
+        if ui.radio(folder_template.0 == template, label).clicked() {
+            folder_template.0 = String::from(template);
         }
     }
+    ui.add(
+        egui::TextEdit::singleline(&mut folder_template.0).hint_text("{key} ({name} - {author})"),
+    );
+    ui.label("{key}, {hash}, {name}, {author} and {uploader} are filled in for each song.");
+    if !folder_template.is_unique() {
+        ui.colored_label(
+            egui::Color32::YELLOW,
+            "Without {key} or {hash} songs could share a folder, so the key is put in front.",
+        );
+    }
+}
+
+fn store_config_ui(ui: &mut egui::Ui, store_config: &mut api::StoreConfig) {
+    if ui
+        .radio(
+            matches!(store_config, api::StoreConfig::Public),
+            "Public beat-sharer database",
+        )
+        .clicked()
+    {
+        *store_config = api::StoreConfig::Public;
+    }
+    if ui
+        .radio(
+            matches!(store_config, api::StoreConfig::Firebase { .. }),
+            "Firebase compatible server",
+        )
+        .clicked()
+        && !matches!(store_config, api::StoreConfig::Firebase { .. })
+    {
+        *store_config = api::StoreConfig::Firebase {
+            addr: String::new(),
+            auth: String::new(),
+        };
+    }
+    if ui
+        .radio(
+            matches!(store_config, api::StoreConfig::Directory { .. }),
+            "Shared folder",
+        )
+        .clicked()
+        && !matches!(store_config, api::StoreConfig::Directory { .. })
+    {
+        *store_config = api::StoreConfig::Directory {
+            path: std::env::current_dir().unwrap_or_default(),
+        };
+    }
 
-    codes
+    match store_config {
+        api::StoreConfig::Public => {}
+        api::StoreConfig::Firebase { addr, auth } => {
+            ui.add(egui::TextEdit::singleline(addr).hint_text("https://example.com"));
+            ui.add(
+                egui::TextEdit::singleline(auth)
+                    .hint_text("Auth token (optional)")
+                    .password(true),
+            );
+        }
+        api::StoreConfig::Directory { path } => {
+            ui.horizontal(|ui| {
+                ui.label(path.display().to_string());
+                if ui.add(egui::Button::new("Change")).clicked() {
+                    if let Some(result) =
+                        tinyfiledialogs::select_folder_dialog("Select Shared Lists Folder", ".")
+                    {
+                        *path = Path::new(&result).to_path_buf();
+                    }
+                }
+            });
+        }
+    }
 }
@@ -0,0 +1,23 @@
+#![forbid(unsafe_code)]
+#![cfg_attr(not(debug_assertions), deny(warnings))] // Forbid warnings in release builds
+#![warn(clippy::all, rust_2018_idioms)]
+
+//! The command line on its own.
+//!
+//! `beat-sharer` is built as a windowed program on Windows, which has no console to print to, so
+//! scripts there use this instead. It takes the same commands.
+
+#[macro_use]
+extern crate dotenv_codegen;
+
+// `is_command` is only needed to decide whether to open the window
+#[allow(dead_code)]
+#[path = "../cli.rs"]
+mod cli;
+
+use beat_sharer::api;
+
+fn main() {
+    api::set_public_auth(dotenv!("secret"));
+    std::process::exit(cli::run(std::env::args().skip(1).collect()));
+}
@@ -0,0 +1,379 @@
+#![forbid(unsafe_code)]
+#![cfg_attr(not(debug_assertions), deny(warnings))] // Forbid warnings in release builds
+#![warn(clippy::all, rust_2018_idioms)]
+
+//! A small self-hosted list server.
+//!
+//! Speaks the subset of the Firebase realtime database REST protocol that beat-sharer uses, so the
+//! app can be pointed at it with the "Firebase compatible server" storage option:
+//!
+//! * `GET /<path>.json` - read a value, `null` if it doesn't exist. Sends an `ETag` header when
+//!   the request has `X-Firebase-ETag: true`.
+//! * `PUT /<path>.json` - write a value. Honours `If-Match`, answering `412` if the value changed.
+//! * `DELETE /<path>.json` - remove a value.
+//!
+//! `<path>` is either `index` (the ID counter), a numeric list ID, or `<id>_pins` for the versions
+//! a list's songs are pinned to. Values are kept as files in the data folder.
+
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH, RETRY_AFTER};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const USAGE: &str =
+    "usage: beat-sharer-server [--addr <ip:port>] [--data <folder>] [--auth <token>] \
+[--max-list-size <bytes>] [--requests-per-minute <n>]";
+const POISONED_MUTEX_MESSAGE: &str =
+    "failed to unlock mutex due to another thread panicking while holding it";
+
+struct Config {
+    addr: SocketAddr,
+    data: PathBuf,
+    auth: Option<String>,
+    max_list_size: usize,
+    requests_per_minute: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            data: PathBuf::from("lists"),
+            auth: None,
+            max_list_size: 256 * 1024,
+            requests_per_minute: 60,
+        }
+    }
+}
+
+impl Config {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut config = Config::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            match arg.as_str() {
+                "--addr" => config.addr = value()?.parse().map_err(|_| "invalid --addr")?,
+                "--data" => config.data = PathBuf::from(value()?),
+                "--auth" => config.auth = Some(value()?),
+                "--max-list-size" => {
+                    config.max_list_size =
+                        value()?.parse().map_err(|_| "invalid --max-list-size")?
+                }
+                "--requests-per-minute" => {
+                    config.requests_per_minute = value()?
+                        .parse()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or("invalid --requests-per-minute")?
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Token bucket per client address. Every request costs one token, buckets refill continuously
+/// up to `requests_per_minute`.
+struct RateLimiter {
+    capacity: f64,
+    per_second: f64,
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_minute: u32) -> Self {
+        Self {
+            capacity: requests_per_minute as f64,
+            per_second: requests_per_minute as f64 / 60.0,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Takes a token for `ip`, or returns how many seconds until one is available.
+    fn take(&self, ip: IpAddr) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().expect(POISONED_MUTEX_MESSAGE);
+        let now = Instant::now();
+        // forget clients whose buckets have refilled so the map doesn't grow forever
+        let (capacity, per_second) = (self.capacity, self.per_second);
+        buckets.retain(|_, (tokens, last)| {
+            *tokens + now.duration_since(*last).as_secs_f64() * per_second < capacity
+        });
+
+        let (tokens, last) = buckets.entry(ip).or_insert((capacity, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * per_second).min(capacity);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - *tokens) / per_second).ceil() as u64)
+        }
+    }
+}
+
+struct State {
+    config: Config,
+    limiter: RateLimiter,
+    // serializes reads and writes so conditional PUTs are atomic
+    files: Mutex<()>,
+}
+
+impl State {
+    fn value_path(&self, path: &str) -> PathBuf {
+        self.config.data.join(format!("{}.json", path))
+    }
+
+    fn read(&self, path: &str) -> io::Result<String> {
+        match std::fs::read_to_string(self.value_path(path)) {
+            Ok(value) => Ok(value),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::from("null")),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write(&self, path: &str, value: &str) -> io::Result<()> {
+        // write then rename so a crash never leaves a half written list behind
+        let tmp = self.value_path(&format!("{}.tmp", path));
+        std::fs::write(&tmp, value)?;
+        std::fs::rename(tmp, self.value_path(path))
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        match std::fs::remove_file(self.value_path(path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn etag(value: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn response(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    response(status, format!("{{\"error\":\"{}\"}}", message))
+}
+
+fn internal_error(err: io::Error) -> Response<Body> {
+    eprintln!("storage error: {}", err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "storage error")
+}
+
+/// Only the ID counter, numeric list IDs and their pins may be touched.
+fn value_name(uri_path: &str) -> Option<&str> {
+    let name = uri_path.strip_prefix('/')?.strip_suffix(".json")?;
+    let id = name.strip_suffix("_pins").unwrap_or(name);
+    if name == "index" || (!id.is_empty() && id.chars().all(|c| c.is_ascii_digit())) {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn authorized(state: &State, query: Option<&str>) -> bool {
+    match &state.config.auth {
+        None => true,
+        Some(auth) => query
+            .unwrap_or_default()
+            .split('&')
+            .any(|pair| pair.strip_prefix("auth=") == Some(auth.as_str())),
+    }
+}
+
+/// Reads the request body, giving up as soon as it exceeds the list size cap.
+async fn read_body(state: &State, req: Request<Body>) -> Result<String, Response<Body>> {
+    let too_large = || error(StatusCode::PAYLOAD_TOO_LARGE, "list too large");
+    let declared = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<usize>().ok());
+    if declared.is_some_and(|len| len > state.config.max_list_size) {
+        return Err(too_large());
+    }
+
+    let mut body = req.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = hyper::body::HttpBody::data(&mut body).await {
+        let chunk = chunk.map_err(|_| error(StatusCode::BAD_REQUEST, "invalid body"))?;
+        if bytes.len() + chunk.len() > state.config.max_list_size {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    String::from_utf8(bytes).map_err(|_| error(StatusCode::BAD_REQUEST, "invalid body"))
+}
+
+/// The counter must be a number (or a string holding one), lists must be JSON strings.
+fn valid_value(name: &str, value: &serde_json::Value) -> bool {
+    if name == "index" {
+        match value {
+            serde_json::Value::Number(n) => n.is_u64(),
+            serde_json::Value::String(s) => s.parse::<u32>().is_ok(),
+            _ => false,
+        }
+    } else {
+        value.is_string()
+    }
+}
+
+async fn handle(state: Arc<State>, ip: IpAddr, req: Request<Body>) -> Response<Body> {
+    if let Err(wait) = state.limiter.take(ip) {
+        let mut response = error(StatusCode::TOO_MANY_REQUESTS, "rate limited");
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(wait));
+        return response;
+    }
+    if !authorized(&state, req.uri().query()) {
+        return error(StatusCode::UNAUTHORIZED, "permission denied");
+    }
+    let name = match value_name(req.uri().path()) {
+        Some(name) => name.to_string(),
+        None => return error(StatusCode::NOT_FOUND, "not found"),
+    };
+    let headers: HeaderMap = req.headers().clone();
+    let method = req.method().clone();
+
+    let body = if method == Method::PUT {
+        match read_body(&state, req).await {
+            Ok(body) => match serde_json::from_str::<serde_json::Value>(&body) {
+                Ok(value) if valid_value(&name, &value) => Some(body),
+                _ => return error(StatusCode::BAD_REQUEST, "invalid data"),
+            },
+            Err(response) => return response,
+        }
+    } else {
+        None
+    };
+
+    let _guard = state.files.lock().expect(POISONED_MUTEX_MESSAGE);
+    let current = match state.read(&name) {
+        Ok(current) => current,
+        Err(err) => return internal_error(err),
+    };
+    let current_etag = etag(&current);
+
+    let if_match = headers.get(IF_MATCH).and_then(|tag| tag.to_str().ok());
+    if if_match.is_some_and(|tag| tag != current_etag) {
+        let mut response = response(StatusCode::PRECONDITION_FAILED, current);
+        response
+            .headers_mut()
+            .insert(ETAG, HeaderValue::from_str(&current_etag).unwrap());
+        return response;
+    }
+
+    let (status, value) = match (method, body) {
+        (Method::GET, _) => (StatusCode::OK, current),
+        (Method::PUT, Some(body)) => {
+            if let Err(err) = state.write(&name, &body) {
+                return internal_error(err);
+            }
+            (StatusCode::OK, body)
+        }
+        (Method::DELETE, _) => {
+            if let Err(err) = state.remove(&name) {
+                return internal_error(err);
+            }
+            (StatusCode::OK, String::from("null"))
+        }
+        _ => return error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+    };
+
+    let wants_etag = headers
+        .get("X-Firebase-ETag")
+        .is_some_and(|value| value == "true");
+    let value_etag = etag(&value);
+    let mut response = response(status, value);
+    if wants_etag {
+        response
+            .headers_mut()
+            .insert(ETAG, HeaderValue::from_str(&value_etag).unwrap());
+    }
+    response
+}
+
+fn main() {
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(err) = std::fs::create_dir_all(&config.data) {
+        eprintln!(
+            "failed to create data folder {}: {}",
+            config.data.display(),
+            err
+        );
+        std::process::exit(1);
+    }
+
+    let addr = config.addr;
+    let serving = format!(
+        "serving lists from {} on http://{}",
+        config.data.display(),
+        addr
+    );
+    let state = Arc::new(State {
+        limiter: RateLimiter::new(config.requests_per_minute),
+        config,
+        files: Mutex::new(()),
+    });
+
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let state = state.clone();
+        let ip = conn.remote_addr().ip();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(state, ip, req).await) }
+            }))
+        }
+    });
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap();
+    // binding registers the listener with the runtime, so it has to happen inside of it
+    let result = runtime.block_on(async {
+        let builder = match Server::try_bind(&addr) {
+            Ok(builder) => builder,
+            // usually the port is already in use
+            Err(err) => {
+                eprintln!("failed to listen on {}: {}", addr, err);
+                std::process::exit(1);
+            }
+        };
+        println!("{}", serving);
+        builder.serve(make_service).await
+    });
+    if let Err(err) = result {
+        eprintln!("server error: {}", err);
+        std::process::exit(1);
+    }
+}
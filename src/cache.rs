@@ -0,0 +1,154 @@
+//! On-disk cache of scanned songs and what BeatSaver told us about them, so startup doesn't
+//! rehash every song and song details are still around offline.
+
+use crate::api::{SongInfo, POISONED_MUTEX_MESSAGE};
+use crate::library::LocalSong;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Bumped whenever what's cached changes meaning, so older caches get thrown away.
+const CACHE_VERSION: u32 = 1;
+const CACHE_FILE_NAME: &str = "cache.json";
+
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct Cache {
+    version: u32,
+    folders: HashMap<PathBuf, CachedFolder>,
+    /// Lowercase version hash to BeatSaver key.
+    keys: HashMap<String, String>,
+    /// Lowercase key to the latest details fetched for it.
+    song_infos: HashMap<String, SongInfo>,
+    /// Set when something changed since the cache was last written.
+    #[serde(skip)]
+    dirty: bool,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct CachedFolder {
+    modified: SystemTime,
+    /// `None` if the folder isn't a song.
+    song: Option<LocalSong>,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(load());
+}
+
+fn cache_path() -> Option<PathBuf> {
+    directories_next::ProjectDirs::from("", "", "beat-sharer")
+        .map(|dirs| dirs.cache_dir().join(CACHE_FILE_NAME))
+}
+
+fn load() -> Cache {
+    let cache = cache_path()
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|bytes| serde_json::from_slice::<Cache>(&bytes).ok());
+    match cache {
+        Some(cache) if cache.version == CACHE_VERSION => cache,
+        _ => Cache {
+            version: CACHE_VERSION,
+            ..Default::default()
+        },
+    }
+}
+
+/// Writes the cache to disk, if anything changed since it was last written.
+pub fn save() -> io::Result<()> {
+    let path = match cache_path() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    // held throughout so two saves can't interleave their writes
+    let mut cache = CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+    if !cache.dirty {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_vec(&*cache)?)?;
+    std::fs::rename(temp_path, path)?;
+    cache.dirty = false;
+    Ok(())
+}
+
+/// The newest modification time of a folder and the files directly in it. Editing, adding or
+/// removing any of a song's files changes it.
+pub fn modified(folder: &Path) -> io::Result<SystemTime> {
+    let mut modified = folder.metadata()?.modified()?;
+    for entry in folder.read_dir()? {
+        modified = modified.max(entry?.metadata()?.modified()?);
+    }
+    Ok(modified)
+}
+
+/// What was read from `folder` when it was last `modified`, `Some(None)` if it wasn't a song.
+/// `None` if the folder has changed since or was never read.
+pub(crate) fn song(folder: &Path, modified: SystemTime) -> Option<Option<LocalSong>> {
+    let cache = CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+    cache
+        .folders
+        .get(folder)
+        .filter(|cached| cached.modified == modified)
+        .map(|cached| cached.song.clone())
+}
+
+pub(crate) fn insert_song(folder: PathBuf, modified: SystemTime, song: Option<LocalSong>) {
+    let mut cache = CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+    cache
+        .folders
+        .insert(folder, CachedFolder { modified, song });
+    cache.dirty = true;
+}
+
+/// Forgets every folder in `parent` that isn't in `present`.
+pub(crate) fn retain_folders(parent: &Path, present: &HashSet<PathBuf>) {
+    let mut cache = CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+    let before = cache.folders.len();
+    cache
+        .folders
+        .retain(|folder, _| folder.parent() != Some(parent) || present.contains(folder));
+    if cache.folders.len() != before {
+        cache.dirty = true;
+    }
+}
+
+/// The BeatSaver key of a lowercase version hash, if it was looked up before.
+pub(crate) fn key(hash: &str) -> Option<String> {
+    let cache = CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+    cache.keys.get(hash).cloned()
+}
+
+pub(crate) fn insert_keys(keys: &HashMap<String, String>) {
+    if keys.is_empty() {
+        return;
+    }
+    let mut cache = CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+    cache
+        .keys
+        .extend(keys.iter().map(|(hash, key)| (hash.clone(), key.clone())));
+    cache.dirty = true;
+}
+
+/// The last details fetched from BeatSaver for `key`, its latest version at the time.
+pub fn song_info(key: &str) -> Option<SongInfo> {
+    let cache = CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+    cache.song_infos.get(&key.to_lowercase()).cloned()
+}
+
+pub(crate) fn insert_song_infos<'a>(song_infos: impl IntoIterator<Item = &'a SongInfo>) {
+    let mut cache = CACHE.lock().expect(POISONED_MUTEX_MESSAGE);
+    for song_info in song_infos {
+        cache
+            .song_infos
+            .insert(song_info.id.to_lowercase(), song_info.clone());
+        cache.dirty = true;
+    }
+}
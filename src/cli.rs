@@ -0,0 +1,261 @@
+use beat_sharer::api;
+use beat_sharer::library::Library;
+use beat_sharer::songcore;
+use beat_sharer::util::{format_bytes, format_duration};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: beat-sharer <command> [options]
+
+commands:
+    scan                list the songs in the songs folder
+    upload              upload the songs folder's list and print its ID
+    download <id>       download every song in a list that isn't already in the songs folder
+    diff <id>           compare a list with the songs folder
+    show <id>           print the keys in a list
+
+options:
+    --dir <folder>      songs folder, defaults to the current folder. if it's the game's
+                        CustomLevels folder, WIP levels and SongCore's extra folders are read too
+    --into <folder>     folder to download into, defaults to --dir
+    --report <file>     after downloading, save what failed to a file, as JSON if it ends in .json
+    --jobs <n>          number of songs to download at once
+    --folder-name <template>
+                        how downloaded songs' folders are named, made of {key}, {hash},
+                        {name}, {author} and {uploader}. defaults to \"{key} ({name} - {author})\".
+                        without {key} or {hash}, the key is put in front
+    --server <address>  use a Firebase compatible server instead of the public database
+    --auth <token>      auth token for --server
+    --lists <folder>    use a shared folder instead of the public database";
+
+/// How often download progress is printed.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+struct Options {
+    dir: PathBuf,
+    into: Option<PathBuf>,
+    report: Option<PathBuf>,
+    jobs: std::num::NonZeroUsize,
+    folder_template: api::FolderTemplate,
+    store_config: api::StoreConfig,
+}
+
+/// Returns true if `command` is one the CLI handles, so `main` knows whether to open the window.
+pub fn is_command(command: &str) -> bool {
+    matches!(
+        command,
+        "scan" | "upload" | "download" | "diff" | "show" | "help" | "--help" | "-h"
+    )
+}
+
+/// Runs a command line invocation, returning the process exit code.
+pub fn run(args: Vec<String>) -> i32 {
+    match run_inner(args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            1
+        }
+    }
+}
+
+fn run_inner(args: Vec<String>) -> Result<i32, String> {
+    let mut args = args.into_iter();
+    let command = args.next().unwrap_or_default();
+    let mut positional = Vec::new();
+    let mut options = Options {
+        dir: std::env::current_dir().map_err(|err| err.to_string())?,
+        into: None,
+        report: None,
+        jobs: api::default_max_concurrent_downloads(),
+        folder_template: api::FolderTemplate::default(),
+        store_config: api::StoreConfig::Public,
+    };
+    let mut auth = String::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--dir" => options.dir = PathBuf::from(value()?),
+            "--into" => options.into = Some(PathBuf::from(value()?)),
+            "--report" => options.report = Some(PathBuf::from(value()?)),
+            "--jobs" => {
+                options.jobs = value()?
+                    .parse()
+                    .map_err(|_| String::from("--jobs must be a positive number"))?
+            }
+            "--folder-name" => options.folder_template = api::FolderTemplate(value()?),
+            "--server" => {
+                options.store_config = api::StoreConfig::Firebase {
+                    addr: value()?,
+                    auth: String::new(),
+                }
+            }
+            "--auth" => auth = value()?,
+            "--lists" => {
+                options.store_config = api::StoreConfig::Directory {
+                    path: PathBuf::from(value()?),
+                }
+            }
+            _ if arg.starts_with("--") => {
+                return Err(format!("unknown option {}\n\n{}", arg, USAGE))
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if let api::StoreConfig::Firebase {
+        auth: store_auth, ..
+    } = &mut options.store_config
+    {
+        *store_auth = auth;
+    }
+    api::set_store(&options.store_config);
+
+    let id = || -> Result<u32, String> {
+        positional
+            .first()
+            .ok_or(format!("{} needs a list ID\n\n{}", command, USAGE))?
+            .parse::<u32>()
+            .map_err(|_| String::from("list IDs are numbers"))
+    };
+
+    match command.as_str() {
+        "scan" => scan(&options),
+        "upload" => upload(&options),
+        "download" => download(&options, id()?),
+        "diff" => diff(&options, id()?),
+        "show" => show(id()?),
+        _ => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+    }
+}
+
+/// Reads every songs folder and looks up the keys of songs that weren't named by key.
+fn local_library(options: &Options) -> Result<Library, String> {
+    let paths: Vec<PathBuf> = songcore::song_folders(&options.dir)
+        .into_iter()
+        .map(|folder| folder.path)
+        .collect();
+    let mut library = Library::scan_all(&paths)
+        .map_err(|err| format!("failed to read {}: {}", options.dir.display(), err))?;
+    let hashes = library.unresolved_hashes();
+    if !hashes.is_empty() {
+        match api::resolve_keys(hashes).blocking_recv() {
+            Ok(Ok(keys)) => library.apply_keys(&keys),
+            // still usable, those songs just can't be matched up by key
+            Ok(Err(err)) => eprintln!("failed to look up some keys: {}", err),
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+    Ok(library)
+}
+
+fn get_list(id: u32) -> Result<Vec<api::SharedSong>, String> {
+    api::get_list(id)
+        .blocking_recv()
+        .map_err(|err| err.to_string())?
+        .map_err(|err| format!("failed to get list {}: {}", id, err))
+}
+
+fn scan(options: &Options) -> Result<i32, String> {
+    for song in local_library(options)?.songs {
+        println!(
+            "{}\t{}\t{} - {}",
+            song.key.as_deref().unwrap_or("-"),
+            song.hash.as_deref().unwrap_or("-"),
+            song.name,
+            song.level_author,
+        );
+    }
+    Ok(0)
+}
+
+fn upload(options: &Options) -> Result<i32, String> {
+    let songs = local_library(options)?.shared_songs();
+    if songs.is_empty() {
+        return Err(format!("found no songs in {}", options.dir.display()));
+    }
+
+    let id = api::get_and_inc_index()
+        .blocking_recv()
+        .map_err(|err| err.to_string())?
+        .map_err(|err| format!("failed to get an ID: {}", err))?;
+    api::put_list(id, songs.clone())
+        .blocking_recv()
+        .map_err(|err| err.to_string())?
+        .map_err(|err| format!("failed to upload list: {}", err))?;
+
+    eprintln!("uploaded {} songs", songs.len());
+    println!("{}", id);
+    Ok(0)
+}
+
+fn download(options: &Options, id: u32) -> Result<i32, String> {
+    let into = options.into.as_ref().unwrap_or(&options.dir);
+    api::clean_staging(into).map_err(|err| err.to_string())?;
+    let library = local_library(options)?;
+    let list = get_list(id)?;
+    let observer = api::download(
+        list,
+        library,
+        into.clone(),
+        options.folder_template.clone(),
+        options.jobs,
+    );
+
+    while observer.downloading() {
+        std::thread::sleep(PROGRESS_INTERVAL);
+        let progress = observer.progress();
+        eprint!(
+            "\r{:3.0}% {} at {}/s, {} left. downloaded {}, downloading {}, failed {}   ",
+            progress.fraction * 100.0,
+            format_bytes(progress.downloaded_bytes),
+            format_bytes(progress.bytes_per_second as u64),
+            progress
+                .eta
+                .map(format_duration)
+                .unwrap_or_else(|| String::from("?")),
+            observer.get_downloaded(),
+            observer.ongoing_downloads().len(),
+            observer.failed_downloads().len(),
+        );
+    }
+    eprintln!();
+
+    let report = observer.report();
+    print!("{}", report);
+    if let Some(path) = &options.report {
+        report
+            .save(path)
+            .map_err(|err| format!("failed to save report to {}: {}", path.display(), err))?;
+    }
+    Ok(if report.failures.is_empty() { 0 } else { 1 })
+}
+
+fn diff(options: &Options, id: u32) -> Result<i32, String> {
+    let local = local_library(options)?.keys();
+    let list = get_list(id)?;
+    let listed: std::collections::HashSet<String> =
+        list.iter().map(|song| song.key.to_lowercase()).collect();
+
+    for song in list
+        .iter()
+        .filter(|song| !local.contains(&song.key.to_lowercase()))
+    {
+        println!("+ {}", song.key);
+    }
+    for key in local.iter().filter(|key| !listed.contains(*key)) {
+        println!("- {}", key);
+    }
+    Ok(0)
+}
+
+fn show(id: u32) -> Result<i32, String> {
+    for song in get_list(id)? {
+        println!("{}", song);
+    }
+    Ok(0)
+}
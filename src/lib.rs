@@ -0,0 +1,22 @@
+#![forbid(unsafe_code)]
+#![cfg_attr(not(debug_assertions), deny(warnings))] // Forbid warnings in release builds
+#![warn(clippy::all, rust_2018_idioms)]
+
+//! Share Beat Saber song lists.
+//!
+//! * [`api::download`] runs the download engine, reporting through a [`api::DownloadObserver`].
+//! * [`api::beatsaver`] talks to BeatSaver directly.
+//! * [`api::ListStore`] is where shared lists are kept, pick one with [`api::set_store`].
+//! * [`library`] reads the songs already installed, remembering what it read in [`cache`].
+//!   [`songcore`] finds every folder they're installed in, [`steam`] finds the game.
+//!
+//! The window lives behind the `gui` feature, depend on this crate with
+//! `default-features = false` to leave it out.
+
+pub mod api;
+pub mod cache;
+pub mod library;
+pub mod map_hash;
+pub mod songcore;
+pub mod steam;
+pub mod util;
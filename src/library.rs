@@ -0,0 +1,294 @@
+use crate::api::{SharedSong, POISONED_MUTEX_MESSAGE};
+use crate::cache;
+use crate::map_hash;
+use crate::util::StringUtils;
+use notify_debouncer_mini::notify::{self, RecommendedWatcher};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long the songs folder has to be left alone before changes are reported, copying a song in
+/// touches it many times.
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A song installed in the songs folder.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct LocalSong {
+    pub path: PathBuf,
+    /// BeatSaver key, from the folder name or looked up by hash.
+    /// `None` until looked up, or if BeatSaver doesn't know the song.
+    pub key: Option<String>,
+    /// SongCore compatible version hash, lowercase hex.
+    /// `None` if the map is in a format that isn't hashed that way.
+    pub hash: Option<String>,
+    pub name: String,
+    pub sub_name: String,
+    pub author: String,
+    /// The mapper.
+    pub level_author: String,
+    pub bpm: f32,
+}
+
+impl LocalSong {
+    /// Reads the song in `folder`, `None` if there's no `Info.dat` so it isn't a song.
+    pub fn read(folder: &Path) -> io::Result<Option<Self>> {
+        let info_path = match map_hash::find_info_dat(folder)? {
+            Some(info_path) => info_path,
+            None => return Ok(None),
+        };
+        let info_bytes = std::fs::read(info_path)?;
+        let json = info_bytes
+            .strip_prefix(b"\xEF\xBB\xBF")
+            .unwrap_or(&info_bytes);
+        let info: serde_json::Value = serde_json::from_slice(json)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        // v2 and v3 maps use the underscored fields, v4 maps moved them around
+        let text = |v2: &str, v4: &[&str]| {
+            info.get(v2)
+                .or_else(|| v4.iter().try_fold(&info, |value, key| value.get(key)))
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let bpm = info
+            .get("_beatsPerMinute")
+            .or_else(|| info.get("audio").and_then(|audio| audio.get("bpm")))
+            .and_then(|bpm| bpm.as_f64())
+            .unwrap_or_default() as f32;
+
+        Ok(Some(Self {
+            path: folder.to_path_buf(),
+            key: key_from_folder_name(folder),
+            hash: map_hash::compute_map_hash(folder)?,
+            name: text("_songName", &["song", "title"]),
+            sub_name: text("_songSubName", &["song", "subTitle"]),
+            author: text("_songAuthorName", &["song", "author"]),
+            level_author: text("_levelAuthorName", &[]),
+            bpm,
+        }))
+    }
+}
+
+/// Every song in a songs folder.
+#[derive(Clone, Debug, Default)]
+pub struct Library {
+    pub songs: Vec<LocalSong>,
+}
+
+impl Library {
+    /// Reads every song folder in `path`. Folders that aren't songs are skipped, as are songs
+    /// that can't be read.
+    ///
+    /// Only folders modified since the last scan are read again, the rest come from the cache.
+    pub fn scan(path: &Path) -> io::Result<Self> {
+        let mut songs = Vec::new();
+        let mut folders = HashSet::new();
+        for entry in path.read_dir()? {
+            let folder = entry?.path();
+            // our staging folder, and anything else hidden
+            let hidden = folder
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_none_or(|name| name.starts_with('.'));
+            if hidden || !folder.is_dir() {
+                continue;
+            }
+            folders.insert(folder.clone());
+            if let Some(song) = read_cached(folder) {
+                songs.push(song);
+            }
+        }
+
+        cache::retain_folders(path, &folders);
+        // it's only a cache, the scan still worked
+        let _ = cache::save();
+        Ok(Self { songs })
+    }
+
+    /// Reads every song in several songs folders as one library.
+    pub fn scan_all(paths: &[PathBuf]) -> io::Result<Self> {
+        let mut songs = Vec::new();
+        for path in paths {
+            songs.append(&mut Self::scan(path)?.songs);
+        }
+        Ok(Self { songs })
+    }
+
+    /// Reads `folders` again, adding, replacing or removing their songs. Keys of songs that
+    /// weren't named by key need looking up again, see [`Library::unresolved_hashes`].
+    pub fn update(&mut self, folders: &[PathBuf]) {
+        let songs = Self::read_folders(folders);
+        self.replace_folders(folders, songs);
+    }
+
+    /// The songs in `folders`, the reading half of [`Library::update`]. Folders that are gone or
+    /// aren't songs are left out.
+    pub fn read_folders(folders: &[PathBuf]) -> Vec<LocalSong> {
+        let songs = folders
+            .iter()
+            .filter(|folder| folder.is_dir())
+            .filter_map(|folder| read_cached(folder.clone()))
+            .collect();
+        let _ = cache::save();
+        songs
+    }
+
+    /// Replaces whatever was in `folders` with `songs`, as read by [`Library::read_folders`].
+    pub fn replace_folders(&mut self, folders: &[PathBuf], mut songs: Vec<LocalSong>) {
+        self.songs.retain(|song| !folders.contains(&song.path));
+        self.songs.append(&mut songs);
+    }
+
+    pub fn len(&self) -> usize {
+        self.songs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+
+    /// Lowercase keys of every song with a known key.
+    pub fn keys(&self) -> HashSet<String> {
+        self.songs
+            .iter()
+            .filter_map(|song| song.key.as_ref())
+            .map(|key| key.to_lowercase())
+            .collect()
+    }
+
+    pub fn hashes(&self) -> HashSet<String> {
+        self.songs
+            .iter()
+            .filter_map(|song| song.hash.clone())
+            .collect()
+    }
+
+    /// Hashes of songs whose key isn't known yet, to look up with `api::resolve_keys`.
+    pub fn unresolved_hashes(&self) -> Vec<String> {
+        self.songs
+            .iter()
+            .filter(|song| song.key.is_none())
+            .filter_map(|song| song.hash.clone())
+            .collect()
+    }
+
+    /// Fills in keys looked up by hash.
+    pub fn apply_keys(&mut self, keys: &HashMap<String, String>) {
+        for song in self.songs.iter_mut().filter(|song| song.key.is_none()) {
+            song.key = song.hash.as_ref().and_then(|hash| keys.get(hash)).cloned();
+        }
+    }
+
+    /// Every song with a known key, pinned to the installed version.
+    pub fn shared_songs(&self) -> Vec<SharedSong> {
+        self.songs
+            .iter()
+            .filter_map(|song| {
+                Some(SharedSong {
+                    key: song.key.clone()?,
+                    hash: song.hash.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Watches songs folders, collecting the song folders that change in it so a [`Library`] can be
+/// kept up to date with [`Library::update`]. Stops watching when dropped.
+pub struct LibraryWatcher {
+    _debouncer: Debouncer<RecommendedWatcher>,
+    changed: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl LibraryWatcher {
+    /// Starts watching `paths`. `on_change` is called from the watcher's thread once changes have
+    /// settled, e.g. to wake up whatever calls [`LibraryWatcher::take_changes`].
+    pub fn new(paths: &[PathBuf], on_change: impl Fn() + Send + 'static) -> notify::Result<Self> {
+        let changed: Arc<Mutex<HashSet<PathBuf>>> = Default::default();
+        let roots = paths.to_vec();
+        let handler_changed = changed.clone();
+        let mut debouncer = new_debouncer(DEBOUNCE_TIMEOUT, move |result: DebounceEventResult| {
+            // errors are usually events the OS dropped, the next ones still get through
+            let events = match result {
+                Ok(events) => events,
+                Err(_) => return,
+            };
+            let folders: HashSet<PathBuf> = events
+                .iter()
+                .filter_map(|event| roots.iter().find_map(|root| song_folder(root, &event.path)))
+                .collect();
+            if !folders.is_empty() {
+                handler_changed
+                    .lock()
+                    .expect(POISONED_MUTEX_MESSAGE)
+                    .extend(folders);
+                on_change();
+            }
+        })?;
+        for path in paths {
+            debouncer
+                .watcher()
+                .watch(path, notify::RecursiveMode::Recursive)?;
+        }
+
+        Ok(Self {
+            _debouncer: debouncer,
+            changed,
+        })
+    }
+
+    /// The song folders that changed since the last call.
+    pub fn take_changes(&self) -> Vec<PathBuf> {
+        self.changed
+            .lock()
+            .expect(POISONED_MUTEX_MESSAGE)
+            .drain()
+            .collect()
+    }
+}
+
+/// The song folder in `root` that `path` is in, `None` for `root` itself and hidden folders.
+fn song_folder(root: &Path, path: &Path) -> Option<PathBuf> {
+    let name = path.strip_prefix(root).ok()?.components().next()?;
+    match name {
+        Component::Normal(name) if !name.to_string_lossy().starts_with('.') => {
+            Some(root.join(name))
+        }
+        _ => None,
+    }
+}
+
+/// Reads the song in `folder` unless it's cached and hasn't changed since.
+fn read_cached(folder: PathBuf) -> Option<LocalSong> {
+    let modified = cache::modified(&folder).ok()?;
+    if let Some(song) = cache::song(&folder, modified) {
+        return song;
+    }
+    // songs that failed to read aren't cached, they might be mid copy
+    let song = LocalSong::read(&folder).ok()?;
+    cache::insert_song(folder, modified, song.clone());
+    song
+}
+
+/// The key from BeatSaver's `"<key> (<name> - <author>)"` folder naming, or a folder named by
+/// just its key.
+fn key_from_folder_name(folder: &Path) -> Option<String> {
+    let filename = folder.file_name()?.to_str()?.to_string();
+
+    let code = match filename.find(" (") {
+        Some(end) => filename.substring(0, end),
+        // key only folders, keys are hex
+        None if filename.chars().all(|c| c.is_ascii_hexdigit()) => filename,
+        None => return None,
+    };
+
+    if !code.is_empty() && code.chars().count() <= 5 {
+        Some(code)
+    } else {
+        None
+    }
+}
@@ -1,21 +1,37 @@
 #![forbid(unsafe_code)]
 #![cfg_attr(not(debug_assertions), deny(warnings))] // Forbid warnings in release builds
 #![warn(clippy::all, rust_2018_idioms)]
-#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] //Hide console window in release builds on Windows, this blocks stdout.
+#![cfg_attr(
+    all(not(debug_assertions), feature = "gui"),
+    windows_subsystem = "windows"
+)] //Hide console window in release builds on Windows, this blocks stdout.
 
-pub mod api;
+#[macro_use]
+extern crate dotenv_codegen;
+
+#[cfg(feature = "gui")]
 mod app;
-pub mod util;
+mod cli;
 
-//pub use app::BeatSharerApp;
+use beat_sharer::api;
 
-#[macro_use]
-extern crate dotenv_codegen;
+//pub use app::BeatSharerApp;
 
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
-    dotenv::dotenv().ok();
+    api::set_public_auth(dotenv!("secret"));
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !cfg!(feature = "gui") || args.first().is_some_and(|command| cli::is_command(command)) {
+        std::process::exit(cli::run(args));
+    }
+
+    #[cfg(feature = "gui")]
+    run_gui();
+}
 
+#[cfg(feature = "gui")]
+fn run_gui() {
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
         "Beat Sharer",
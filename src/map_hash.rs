@@ -0,0 +1,142 @@
+//! The hash BeatSaver and SongCore identify a map version by.
+
+use sha1::{Digest, Sha1};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(serde::Deserialize)]
+struct InfoDat {
+    #[serde(rename = "_difficultyBeatmapSets")]
+    difficulty_beatmap_sets: Option<Vec<DifficultyBeatmapSet>>,
+}
+
+#[derive(serde::Deserialize)]
+struct DifficultyBeatmapSet {
+    #[serde(rename = "_difficultyBeatmaps", default)]
+    difficulty_beatmaps: Vec<DifficultyBeatmap>,
+}
+
+#[derive(serde::Deserialize)]
+struct DifficultyBeatmap {
+    #[serde(rename = "_beatmapFilename")]
+    beatmap_filename: String,
+}
+
+/// Finds a map's `Info.dat`, whatever its capitalization.
+pub fn find_info_dat(song_dir: &Path) -> io::Result<Option<PathBuf>> {
+    for entry in song_dir.read_dir()? {
+        let path = entry?.path();
+        let is_info = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.eq_ignore_ascii_case("info.dat"));
+        if is_info && path.is_file() {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Computes the lowercase hex SHA1 of `Info.dat` followed by every difficulty file, in the
+/// order `Info.dat` lists them.
+///
+/// Returns `None` if `Info.dat` is in a format that isn't hashed this way, and fails if there's
+/// no `Info.dat` at all.
+pub fn compute_map_hash(song_dir: &Path) -> io::Result<Option<String>> {
+    let info_path = find_info_dat(song_dir)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no Info.dat"))?;
+    let info_bytes = std::fs::read(info_path)?;
+    // some editors write a byte order mark, it's part of the hash but not valid JSON
+    let json = info_bytes
+        .strip_prefix(b"\xEF\xBB\xBF")
+        .unwrap_or(&info_bytes);
+    let info: InfoDat = serde_json::from_slice(json)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let sets = match info.difficulty_beatmap_sets {
+        Some(sets) => sets,
+        None => return Ok(None),
+    };
+
+    let mut hasher = Sha1::new();
+    hasher.update(&info_bytes);
+    for beatmap in sets.iter().flat_map(|set| &set.difficulty_beatmaps) {
+        // Info.dat comes from the map's author, don't let it read anything outside the map
+        let filename = Path::new(&beatmap.beatmap_filename);
+        if !filename
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "difficulty file outside of the map folder",
+            ));
+        }
+        hasher.update(std::fs::read(song_dir.join(filename))?);
+    }
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO_DAT: &[u8] = br#"{"_version":"2.0.0","_songName":"Song","_difficultyBeatmapSets":[{"_beatmapCharacteristicName":"Standard","_difficultyBeatmaps":[{"_difficulty":"Easy","_beatmapFilename":"Easy.dat"},{"_difficulty":"Expert","_beatmapFilename":"Expert.dat"}]}]}"#;
+
+    /// A map with `info` as its `Info.dat` and two difficulties.
+    fn map(info_name: &str, info: &[u8]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(info_name), info).unwrap();
+        std::fs::write(dir.path().join("Easy.dat"), r#"{"_notes":[]}"#).unwrap();
+        std::fs::write(dir.path().join("Expert.dat"), r#"{"_notes":[{"_time":1}]}"#).unwrap();
+        dir
+    }
+
+    #[test]
+    fn hashes_info_and_difficulties_in_order() {
+        // SHA1 of Info.dat, Easy.dat and Expert.dat one after the other
+        let dir = map("Info.dat", INFO_DAT);
+        assert_eq!(
+            compute_map_hash(dir.path()).unwrap().as_deref(),
+            Some("17c9c2160919290c60657e20f40c369c32ee4a8d")
+        );
+    }
+
+    #[test]
+    fn hashes_the_byte_order_mark() {
+        let info = [b"\xEF\xBB\xBF", INFO_DAT].concat();
+        let dir = map("info.dat", &info);
+        assert_eq!(
+            compute_map_hash(dir.path()).unwrap().as_deref(),
+            Some("a71601b3e555c399cf2cf6b2318527d339128259")
+        );
+    }
+
+    #[test]
+    fn doesnt_hash_newer_formats() {
+        let dir = map(
+            "Info.dat",
+            br#"{"version":"4.0.0","song":{"title":"Song"}}"#,
+        );
+        assert_eq!(compute_map_hash(dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn fails_without_info_or_difficulties() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(compute_map_hash(dir.path()).is_err());
+
+        let dir = map("Info.dat", INFO_DAT);
+        std::fs::remove_file(dir.path().join("Expert.dat")).unwrap();
+        assert!(compute_map_hash(dir.path()).is_err());
+    }
+
+    #[test]
+    fn doesnt_read_outside_the_map() {
+        let info = String::from_utf8(INFO_DAT.to_vec())
+            .unwrap()
+            .replace("Easy.dat", "../Easy.dat");
+        let dir = map("Info.dat", info.as_bytes());
+        let err = compute_map_hash(dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
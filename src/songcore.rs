@@ -0,0 +1,123 @@
+//! Song folders SongCore loads songs from besides `CustomLevels`.
+
+use crate::steam::BEAT_SABER_APP_ID;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where SongCore keeps extra song folders, relative to the game folder.
+const FOLDERS_XML: &str = "UserData/SongCore/folders.xml";
+
+/// A folder songs are loaded from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SongFolder {
+    pub name: String,
+    pub path: PathBuf,
+    /// Work in progress levels, only playable in practice mode.
+    pub wip: bool,
+}
+
+/// Every folder the game loads songs from, given its `CustomLevels` folder, which comes first.
+/// Other folders that don't exist are left out. If `custom_levels` isn't in a game folder, it's
+/// the only folder.
+pub fn song_folders(custom_levels: &Path) -> Vec<SongFolder> {
+    let mut folders = vec![SongFolder {
+        name: String::from("Custom Levels"),
+        path: custom_levels.to_path_buf(),
+        wip: false,
+    }];
+    let data = custom_levels.parent();
+    let game = match data.and_then(Path::parent) {
+        Some(game) if data.and_then(Path::file_name) == Some("Beat Saber_Data".as_ref()) => game,
+        _ => return folders,
+    };
+
+    let mut extra = vec![SongFolder {
+        name: String::from("WIP Levels"),
+        path: game.join("Beat Saber_Data").join("CustomWIPLevels"),
+        wip: true,
+    }];
+    // a broken folders.xml just means SongCore won't load those folders either
+    extra.extend(read_folders_xml(game).unwrap_or_default());
+
+    let mut seen = vec![custom_levels.canonicalize().ok()];
+    for folder in extra {
+        let path = folder.path.canonicalize().ok();
+        if path.is_some() && folder.path.is_dir() && !seen.contains(&path) {
+            seen.push(path);
+            folders.push(folder);
+        }
+    }
+    folders
+}
+
+fn read_folders_xml(game: &Path) -> io::Result<Vec<SongFolder>> {
+    let text = match std::fs::read_to_string(game.join(FOLDERS_XML)) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let text = text.trim_start_matches('\u{feff}');
+    let document = roxmltree::Document::parse(text)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    let folders = document
+        .descendants()
+        .filter(|node| node.has_tag_name("folder"))
+        .filter_map(|folder| {
+            let field = |name: &str| {
+                folder
+                    .children()
+                    .find(|node| node.has_tag_name(name))
+                    .and_then(|node| node.text())
+                    .map(str::trim)
+                    .unwrap_or_default()
+            };
+            let path = field("Path");
+            if path.is_empty() {
+                return None;
+            }
+            Some(SongFolder {
+                name: String::from(field("Name")),
+                path: native_path(game, path),
+                wip: field("WIP").eq_ignore_ascii_case("true"),
+            })
+        })
+        .collect();
+    Ok(folders)
+}
+
+/// SongCore writes Windows paths. Under Proton those drives live in the game's Wine prefix, with
+/// `Z:` being the Linux root.
+#[cfg(not(windows))]
+fn native_path(game: &Path, path: &str) -> PathBuf {
+    let drive = match path.as_bytes() {
+        [letter, b':', b'\\' | b'/', ..] if letter.is_ascii_alphabetic() => {
+            (*letter as char).to_ascii_lowercase()
+        }
+        _ => return game.join(path.replace('\\', "/")),
+    };
+    let rest = path[3..].replace('\\', "/");
+    // <library>/steamapps/common/Beat Saber -> <library>/steamapps/compatdata/620980/pfx
+    let dosdevices = game
+        .parent()
+        .and_then(Path::parent)
+        .map(|steamapps| {
+            steamapps
+                .join("compatdata")
+                .join(BEAT_SABER_APP_ID)
+                .join("pfx")
+                .join("dosdevices")
+                .join(format!("{}:", drive))
+        })
+        .filter(|dosdevices| dosdevices.exists());
+    match dosdevices {
+        Some(dosdevices) => dosdevices.join(rest),
+        None if drive == 'z' => Path::new("/").join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+#[cfg(windows)]
+fn native_path(game: &Path, path: &str) -> PathBuf {
+    game.join(path)
+}
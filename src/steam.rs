@@ -0,0 +1,321 @@
+//! Finds Beat Saber installs through Steam's library folders.
+
+use std::path::{Path, PathBuf};
+
+/// Steam's app id for Beat Saber. Proton keeps the game's Windows drives under it too.
+pub(crate) const BEAT_SABER_APP_ID: &str = "620980";
+
+/// A Beat Saber install in a Steam library.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeatSaberInstall {
+    /// The game folder, e.g. `<library>/steamapps/common/Beat Saber`.
+    pub path: PathBuf,
+    /// The Steam library it's in.
+    pub library: PathBuf,
+}
+
+impl BeatSaberInstall {
+    pub fn custom_levels(&self) -> PathBuf {
+        self.path.join("Beat Saber_Data").join("CustomLevels")
+    }
+}
+
+/// Every Beat Saber install in every Steam library, following the Steam installs we know to look
+/// for: native, Flatpak and Snap on Linux.
+pub fn find_installs() -> Vec<BeatSaberInstall> {
+    let mut libraries: Vec<PathBuf> = Vec::new();
+    for root in steam_roots() {
+        libraries.push(root.clone());
+        libraries.extend(library_folders(&root));
+    }
+
+    let mut seen = Vec::new();
+    let mut installs = Vec::new();
+    for library in libraries {
+        // ~/.steam/steam is usually a link to ~/.local/share/Steam
+        let canonical = match library.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(_) => continue,
+        };
+        if seen.contains(&canonical) {
+            continue;
+        }
+        if let Some(install) = find_install(&canonical) {
+            installs.push(install);
+        }
+        seen.push(canonical);
+    }
+    installs
+}
+
+fn steam_roots() -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    if cfg!(windows) {
+        roots.push(PathBuf::from(r"C:\Program Files (x86)\Steam"));
+        roots.push(PathBuf::from(r"C:\Program Files\Steam"));
+    } else if let Some(dirs) = directories_next::BaseDirs::new() {
+        let home = dirs.home_dir();
+        roots.push(home.join(".steam/steam"));
+        roots.push(home.join(".steam/root"));
+        roots.push(home.join(".local/share/Steam"));
+        roots.push(home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"));
+        roots.push(home.join(".var/app/com.valvesoftware.Steam/data/Steam"));
+        roots.push(home.join("snap/steam/common/.local/share/Steam"));
+    }
+    roots.retain(|root| root.join("steamapps").is_dir());
+    roots
+}
+
+/// The libraries listed in a Steam root's `libraryfolders.vdf`.
+fn library_folders(root: &Path) -> Vec<PathBuf> {
+    let text = match std::fs::read_to_string(root.join("steamapps/libraryfolders.vdf")) {
+        Ok(text) => text,
+        Err(_) => return Vec::new(),
+    };
+    let entries = parse_vdf(&text);
+    let folders = match get(&entries, "libraryfolders") {
+        Some(Value::Section(folders)) => folders,
+        _ => return Vec::new(),
+    };
+
+    folders
+        .iter()
+        // other keys are Steam's own bookkeeping
+        .filter(|(key, _)| key.chars().all(|c| c.is_ascii_digit()))
+        .filter_map(|(_, folder)| match folder {
+            // older Steam versions only list the path
+            Value::Text(path) => Some(PathBuf::from(path)),
+            Value::Section(folder) => match get(folder, "path") {
+                Some(Value::Text(path)) => Some(PathBuf::from(path)),
+                _ => None,
+            },
+        })
+        .collect()
+}
+
+fn find_install(library: &Path) -> Option<BeatSaberInstall> {
+    let steamapps = library.join("steamapps");
+    let manifest = format!("appmanifest_{}.acf", BEAT_SABER_APP_ID);
+    let text = std::fs::read_to_string(steamapps.join(manifest)).ok()?;
+    let entries = parse_vdf(&text);
+    let install_dir = match get(&entries, "AppState") {
+        Some(Value::Section(app)) => match get(app, "installdir") {
+            Some(Value::Text(install_dir)) => install_dir.clone(),
+            _ => String::from("Beat Saber"),
+        },
+        _ => return None,
+    };
+
+    let path = steamapps.join("common").join(install_dir);
+    path.is_dir().then(|| BeatSaberInstall {
+        path,
+        library: library.to_path_buf(),
+    })
+}
+
+/// A value in Valve's KeyValues text format.
+enum Value {
+    Text(String),
+    Section(Vec<(String, Value)>),
+}
+
+enum Token {
+    Text(String),
+    Open,
+    Close,
+}
+
+/// Keys are case insensitive.
+fn get<'a>(entries: &'a [(String, Value)], key: &str) -> Option<&'a Value> {
+    entries
+        .iter()
+        .find(|(entry_key, _)| entry_key.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value)
+}
+
+/// Parses as much of `text` as makes sense, Steam's files are never more than nested quoted
+/// strings.
+fn parse_vdf(text: &str) -> Vec<(String, Value)> {
+    let mut tokens = tokenize(text).into_iter();
+    parse_section(&mut tokens)
+}
+
+fn parse_section(tokens: &mut impl Iterator<Item = Token>) -> Vec<(String, Value)> {
+    let mut entries = Vec::new();
+    while let Some(token) = tokens.next() {
+        let key = match token {
+            Token::Text(key) => key,
+            Token::Open => continue,
+            Token::Close => break,
+        };
+        match tokens.next() {
+            Some(Token::Text(value)) => entries.push((key, Value::Text(value))),
+            Some(Token::Open) => entries.push((key, Value::Section(parse_section(tokens)))),
+            Some(Token::Close) | None => break,
+        }
+    }
+    entries
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().take_while(|c| *c != '\n').for_each(drop);
+            }
+            '"' => {
+                let mut token = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => token.push('\n'),
+                            Some('t') => token.push('\t'),
+                            Some(escaped) => token.push(escaped),
+                            None => break,
+                        },
+                        _ => token.push(c),
+                    }
+                }
+                tokens.push(Token::Text(token));
+            }
+            c if c.is_whitespace() => {}
+            // unquoted text, and platform conditions like [$WIN32] that we don't need
+            c => {
+                let mut token = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"{}\"".contains(*c)) {
+                    token.push(c);
+                }
+                if !token.starts_with('[') {
+                    tokens.push(Token::Text(token));
+                }
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A folder laid out like a Steam library, with nothing installed.
+    fn steam_library() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("steamapps")).unwrap();
+        dir
+    }
+
+    fn text<'a>(entries: &'a [(String, Value)], key: &str) -> Option<&'a str> {
+        match get(entries, key) {
+            Some(Value::Text(text)) => Some(text),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_nested_sections_escapes_and_comments() {
+        let entries = parse_vdf(
+            r#"
+            // a comment
+            "AppState"
+            {
+                "appid"     "620980"
+                "installdir"    "Beat \"Saber\""
+                "path"  "C:\\Games\\Steam"
+                "UserConfig" { "language" "english" }
+                "flag" "1" [$WIN32]
+            }
+            "#,
+        );
+        let app = match get(&entries, "appstate") {
+            Some(Value::Section(app)) => app,
+            _ => panic!("no AppState section"),
+        };
+        assert_eq!(text(app, "appid"), Some("620980"));
+        assert_eq!(text(app, "InstallDir"), Some("Beat \"Saber\""));
+        assert_eq!(text(app, "path"), Some(r"C:\Games\Steam"));
+        assert_eq!(text(app, "flag"), Some("1"));
+        assert!(matches!(get(app, "UserConfig"), Some(Value::Section(_))));
+    }
+
+    #[test]
+    fn reads_new_library_folders() {
+        let dir = steam_library();
+        let root = dir.path();
+        std::fs::write(
+            root.join("steamapps/libraryfolders.vdf"),
+            r#"
+            "libraryfolders"
+            {
+                "0"
+                {
+                    "path"      "/home/user/.local/share/Steam"
+                    "apps" { "620980" "1234" }
+                }
+                "1"
+                {
+                    "path"      "/mnt/games/SteamLibrary"
+                    "label"     ""
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            library_folders(root),
+            [
+                PathBuf::from("/home/user/.local/share/Steam"),
+                PathBuf::from("/mnt/games/SteamLibrary")
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_old_library_folders() {
+        let dir = steam_library();
+        let root = dir.path();
+        std::fs::write(
+            root.join("steamapps/libraryfolders.vdf"),
+            r#"
+            "LibraryFolders"
+            {
+                "TimeNextStatsReport"   "1600000000"
+                "ContentStatsID"        "-123"
+                "1"     "D:\\SteamLibrary"
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(library_folders(root), [PathBuf::from(r"D:\SteamLibrary")]);
+    }
+
+    #[test]
+    fn finds_an_install_from_its_manifest() {
+        let dir = steam_library();
+        let library = dir.path();
+        assert_eq!(find_install(library), None);
+
+        std::fs::write(
+            library.join("steamapps/appmanifest_620980.acf"),
+            r#""AppState" { "appid" "620980" "InstallDir" "Beat Saber" }"#,
+        )
+        .unwrap();
+        // the manifest alone isn't an install
+        assert_eq!(find_install(library), None);
+
+        let path = library.join("steamapps/common/Beat Saber");
+        std::fs::create_dir_all(&path).unwrap();
+        assert_eq!(
+            find_install(library),
+            Some(BeatSaberInstall {
+                path,
+                library: library.to_path_buf()
+            })
+        );
+    }
+}
@@ -7,3 +7,119 @@ impl StringUtils for String {
         self.chars().skip(start).take(len - start).collect()
     }
 }
+
+/// Longest folder name we create, in UTF-8 bytes. Well under the 255 bytes (or UTF-16 units on
+/// NTFS, never more than the UTF-8 bytes) filesystems allow, so the files inside still fit in
+/// Windows' path limit.
+const MAX_FOLDER_NAME_BYTES: usize = 128;
+
+/// Names Windows won't let a file or folder have, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turns `name` into something safe to use as a single folder name on any filesystem we might
+/// be writing to, including NTFS drives mounted on Linux.
+///
+/// Path separators and characters Windows forbids become `_`, so the result can never point
+/// outside the folder it's joined onto.
+pub fn sanitize_folder_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let mut end = sanitized.len().min(MAX_FOLDER_NAME_BYTES);
+    while !sanitized.is_char_boundary(end) {
+        end -= 1;
+    }
+    sanitized.truncate(end);
+
+    // Windows silently drops trailing dots and spaces, which would change the name under us
+    sanitized = sanitized
+        .trim_start()
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string();
+
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        sanitized.insert(0, '_');
+    }
+
+    // all dots (".", "..") were trimmed away above
+    if sanitized.is_empty() {
+        sanitized.push('_');
+    }
+    sanitized
+}
+
+/// A byte count for people, e.g. `12.3 MB`.
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=999_999 => format!("{:.0} KB", bytes as f64 / 1_000.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_000_000.0),
+    }
+}
+
+/// A duration for people, e.g. `1:05`.
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=3599 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        _ => format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_separators_and_forbidden_characters() {
+        assert_eq!(sanitize_folder_name("a/b\\c:d*e?f"), "a_b_c_d_e_f");
+        assert_eq!(sanitize_folder_name("../../etc"), ".._.._etc");
+        assert_eq!(sanitize_folder_name("tab\there"), "tab_here");
+    }
+
+    #[test]
+    fn trims_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_folder_name("  Song... "), "Song");
+        assert_eq!(sanitize_folder_name(".."), "_");
+        assert_eq!(sanitize_folder_name(""), "_");
+    }
+
+    #[test]
+    fn prefixes_reserved_names() {
+        assert_eq!(sanitize_folder_name("CON"), "_CON");
+        assert_eq!(sanitize_folder_name("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize_folder_name("CONSOLE"), "CONSOLE");
+    }
+
+    #[test]
+    fn truncates_by_bytes_at_a_char_boundary() {
+        let ascii = sanitize_folder_name(&"a".repeat(300));
+        assert_eq!(ascii.len(), MAX_FOLDER_NAME_BYTES);
+
+        let japanese = sanitize_folder_name(&"曲".repeat(200));
+        assert!(japanese.len() <= MAX_FOLDER_NAME_BYTES);
+        assert!(japanese.chars().all(|c| c == '曲'));
+        // NTFS counts UTF-16 units
+        assert!(japanese.encode_utf16().count() <= 255);
+
+        let emoji = sanitize_folder_name(&"🎵".repeat(200));
+        assert!(emoji.len() <= MAX_FOLDER_NAME_BYTES);
+        assert!(emoji.encode_utf16().count() <= 255);
+    }
+}